@@ -0,0 +1,1243 @@
+//! Verification of the Authenticode signatures embedded in MSI and CAB files.
+//!
+//! An Authenticode signature is a PKCS#7 `SignedData` whose content is a
+//! `SpcIndirectDataContent` holding a digest of the signed file, calculated
+//! over every part of the file other than the signature itself. The digests
+//! of MSI and CAB files are calculated the same way as
+//! [osslsigncode](https://github.com/mtrojnar/osslsigncode) does.
+//!
+//! Only RSA signatures are supported. The validity periods of certificates
+//! are not checked, as signed files remain valid after their signing
+//! certificate expires due to their timestamp countersignatures, which are
+//! not checked either.
+//!
+//! See <https://download.microsoft.com/download/9/c/5/9c5b2167-8017-4bae-9fde-d599bac8184a/Authenticode_PE.docx>
+
+use crate::{Error, Path};
+use anyhow::Context as _;
+use sha2::digest::DynDigest;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The certificate that Authenticode signatures are verified against by
+/// default, the intermediate that Microsoft uses to issue its code signing
+/// certificates, as Microsoft's root certificates are not included in
+/// signatures
+const MICROSOFT_CODE_SIGNING_PCA_2011: &str =
+    include_str!("certs/microsoft-code-signing-pca-2011.pem");
+
+/// The stream in the root of an MSI that contains its signature
+const DIGITAL_SIGNATURE: &str = "\u{5}DigitalSignature";
+/// The optional stream in the root of an MSI that contains a digest of the
+/// metadata of every stream and storage in the MSI
+const MSI_DIGITAL_SIGNATURE_EX: &str = "\u{5}MsiDigitalSignatureEx";
+
+/// The maximum number of certificates between a signing certificate and a
+/// trusted root
+const MAX_CHAIN_LEN: usize = 8;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+const TAG_CONTEXT_1: u8 = 0xa1;
+const TAG_CONTEXT_3: u8 = 0xa3;
+
+// The encoded contents of the object identifiers we need
+
+/// 1.2.840.113549.1.7.2
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+/// 1.3.6.1.4.1.311.2.1.4
+const OID_SPC_INDIRECT_DATA: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x04];
+/// 1.2.840.113549.1.9.3
+const OID_CONTENT_TYPE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x03];
+/// 1.2.840.113549.1.9.4
+const OID_MESSAGE_DIGEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x04];
+/// 1.2.840.113549.1.1.1
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+/// 2.5.29.19
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+/// 2.5.29.37
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+/// 2.5.29.37.0
+const OID_ANY_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25, 0x00];
+/// 1.3.6.1.5.5.7.3.3
+const OID_CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+
+/// A DER encoded value
+#[derive(Copy, Clone)]
+struct Tlv<'der> {
+    tag: u8,
+    /// The contents of the value, without its tag and length
+    value: &'der [u8],
+    /// The entire encoding of the value
+    raw: &'der [u8],
+}
+
+impl<'der> Tlv<'der> {
+    /// Reads the values contained in a constructed value
+    fn contents(&self) -> Der<'der> {
+        Der(self.value)
+    }
+}
+
+/// A minimal reader of consecutive DER values, only the subset of DER used
+/// by Authenticode signatures is supported
+struct Der<'der>(&'der [u8]);
+
+impl<'der> Der<'der> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn next(&mut self) -> Result<Tlv<'der>, Error> {
+        let data = self.0;
+        anyhow::ensure!(data.len() >= 2, "truncated DER value");
+
+        let tag = data[0];
+        anyhow::ensure!(tag & 0x1f != 0x1f, "unsupported DER tag {:#x}", tag);
+
+        let (len, header_len) = match data[1] {
+            len @ 0..=0x7f => (len as usize, 2),
+            0x81..=0x84 => {
+                let num = (data[1] & 0x7f) as usize;
+                let len = data
+                    .get(2..2 + num)
+                    .context("truncated DER length")?
+                    .iter()
+                    .fold(0, |len, byte| len << 8 | *byte as usize);
+                (len, 2 + num)
+            }
+            other => anyhow::bail!("unsupported DER length {:#x}", other),
+        };
+
+        let raw = data
+            .get(..header_len + len)
+            .context("truncated DER value")?;
+        self.0 = &data[raw.len()..];
+
+        Ok(Tlv {
+            tag,
+            value: &raw[header_len..],
+            raw,
+        })
+    }
+
+    /// Reads the next value, which must have the specified tag
+    fn expect(&mut self, tag: u8) -> Result<Tlv<'der>, Error> {
+        let tlv = self.next()?;
+        anyhow::ensure!(
+            tlv.tag == tag,
+            "expected DER tag {:#x}, found {:#x}",
+            tag,
+            tlv.tag
+        );
+        Ok(tlv)
+    }
+
+    /// Reads the next value only if it has the specified tag
+    fn optional(&mut self, tag: u8) -> Result<Option<Tlv<'der>>, Error> {
+        if self.0.first() == Some(&tag) {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The digest algorithms supported in signatures
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// Parses the algorithm from an `AlgorithmIdentifier`, which can either be
+    /// a digest algorithm or an RSA signature algorithm using a digest
+    fn from_identifier(id: Tlv<'_>) -> Result<Self, Error> {
+        let oid = id.contents().expect(TAG_OID)?.value;
+
+        Ok(match oid {
+            // 1.3.14.3.2.26, 1.2.840.113549.1.1.5
+            [0x2b, 0x0e, 0x03, 0x02, 0x1a]
+            | [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05] => Self::Sha1,
+            // 2.16.840.1.101.3.4.2.1, 1.2.840.113549.1.1.11
+            [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01]
+            | [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b] => Self::Sha256,
+            // 2.16.840.1.101.3.4.2.2, 1.2.840.113549.1.1.12
+            [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02]
+            | [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c] => Self::Sha384,
+            // 2.16.840.1.101.3.4.2.3, 1.2.840.113549.1.1.13
+            [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03]
+            | [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d] => Self::Sha512,
+            other => anyhow::bail!("unsupported digest algorithm {:02x?}", other),
+        })
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            Self::Sha1 => Box::new(<sha1::Sha1 as sha2::Digest>::new()),
+            Self::Sha256 => Box::new(<sha2::Sha256 as sha2::Digest>::new()),
+            Self::Sha384 => Box::new(<sha2::Sha384 as sha2::Digest>::new()),
+            Self::Sha512 => Box::new(<sha2::Sha512 as sha2::Digest>::new()),
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> Box<[u8]> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    fn padding(self) -> rsa::Pkcs1v15Sign {
+        match self {
+            Self::Sha1 => rsa::Pkcs1v15Sign::new::<sha1::Sha1>(),
+            Self::Sha256 => rsa::Pkcs1v15Sign::new::<sha2::Sha256>(),
+            Self::Sha384 => rsa::Pkcs1v15Sign::new::<sha2::Sha384>(),
+            Self::Sha512 => rsa::Pkcs1v15Sign::new::<sha2::Sha512>(),
+        }
+    }
+}
+
+/// Verifies an RSA PKCS#1 v1.5 signature of the data
+fn verify_rsa(
+    public_key: &[u8],
+    alg: DigestAlgorithm,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    use rsa::pkcs8::DecodePublicKey;
+
+    let key = rsa::RsaPublicKey::from_public_key_der(public_key)
+        .context("unsupported public key, only RSA keys are supported")?;
+    key.verify(alg.padding(), &alg.digest(data), signature)
+        .context("signature is invalid")
+}
+
+/// The parts of an X.509 certificate that are needed to verify a chain
+struct Certificate<'der> {
+    raw: &'der [u8],
+    /// The `TBSCertificate` that is signed by the issuer
+    tbs: &'der [u8],
+    signature_alg: DigestAlgorithm,
+    signature: &'der [u8],
+    serial: &'der [u8],
+    issuer: &'der [u8],
+    subject: &'der [u8],
+    /// The encoded `SubjectPublicKeyInfo`
+    public_key: &'der [u8],
+    /// If the certificate can issue other certificates
+    is_ca: bool,
+    /// If the certificate can be used for code signing, ie. its extended key
+    /// usage is not restricted to other purposes
+    code_signing: bool,
+}
+
+impl<'der> Certificate<'der> {
+    fn parse(cert: Tlv<'der>) -> Result<Self, Error> {
+        let mut contents = cert.contents();
+        let tbs = contents.expect(TAG_SEQUENCE)?;
+        let signature_alg = DigestAlgorithm::from_identifier(contents.expect(TAG_SEQUENCE)?)?;
+        let signature = bit_string(contents.expect(TAG_BIT_STRING)?)?;
+
+        let mut fields = tbs.contents();
+        fields.optional(TAG_CONTEXT_0)?;
+        let serial = fields.expect(TAG_INTEGER)?.value;
+        let _signature_alg = fields.expect(TAG_SEQUENCE)?;
+        let issuer = fields.expect(TAG_SEQUENCE)?.raw;
+        let _validity = fields.expect(TAG_SEQUENCE)?;
+        let subject = fields.expect(TAG_SEQUENCE)?.raw;
+        let public_key = fields.expect(TAG_SEQUENCE)?.raw;
+
+        let mut is_ca = false;
+        let mut code_signing = true;
+
+        while !fields.is_empty() {
+            let field = fields.next()?;
+            if field.tag != TAG_CONTEXT_3 {
+                continue;
+            }
+
+            let mut extensions = field.contents().expect(TAG_SEQUENCE)?.contents();
+            while !extensions.is_empty() {
+                let mut extension = extensions.expect(TAG_SEQUENCE)?.contents();
+                let oid = extension.expect(TAG_OID)?.value;
+                extension.optional(TAG_BOOLEAN)?;
+                let value = extension.expect(TAG_OCTET_STRING)?;
+
+                match oid {
+                    OID_BASIC_CONSTRAINTS => {
+                        let mut constraints = value.contents().expect(TAG_SEQUENCE)?.contents();
+                        if let Some(ca) = constraints.optional(TAG_BOOLEAN)? {
+                            is_ca = ca.value != [0];
+                        }
+                    }
+                    OID_EXTENDED_KEY_USAGE => {
+                        let mut usages = value.contents().expect(TAG_SEQUENCE)?.contents();
+                        code_signing = false;
+                        while !usages.is_empty() {
+                            let usage = usages.expect(TAG_OID)?.value;
+                            if usage == OID_CODE_SIGNING || usage == OID_ANY_EXTENDED_KEY_USAGE {
+                                code_signing = true;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            raw: cert.raw,
+            tbs: tbs.raw,
+            signature_alg,
+            signature,
+            serial,
+            issuer,
+            subject,
+            public_key,
+            is_ca,
+            code_signing,
+        })
+    }
+
+    /// Verifies that this certificate was issued by the specified certificate
+    fn verify_issued_by(&self, issuer: &Certificate<'_>) -> Result<(), Error> {
+        anyhow::ensure!(
+            self.issuer == issuer.subject,
+            "'{}' was not issued by '{}'",
+            self.name(),
+            issuer.name()
+        );
+
+        verify_rsa(
+            issuer.public_key,
+            self.signature_alg,
+            self.tbs,
+            self.signature,
+        )
+        .with_context(|| format!("'{}' was not signed by '{}'", self.name(), issuer.name()))
+    }
+
+    /// The common name of the subject, for use in errors
+    fn name(&self) -> String {
+        common_name(self.subject).unwrap_or_else(|| "<unknown>".to_owned())
+    }
+}
+
+/// Retrieves the common name of an X.509 `Name`, if it has one
+fn common_name(name: &[u8]) -> Option<String> {
+    let mut rdns = Der(name).expect(TAG_SEQUENCE).ok()?.contents();
+    while !rdns.is_empty() {
+        let mut attributes = rdns.expect(TAG_SET).ok()?.contents();
+        while !attributes.is_empty() {
+            let mut attribute = attributes.expect(TAG_SEQUENCE).ok()?.contents();
+            if attribute.expect(TAG_OID).ok()?.value == OID_COMMON_NAME {
+                return Some(String::from_utf8_lossy(attribute.next().ok()?.value).into_owned());
+            }
+        }
+    }
+
+    None
+}
+
+/// Retrieves the contents of a bit string, which must not have any unused bits
+fn bit_string(bits: Tlv<'_>) -> Result<&[u8], Error> {
+    match bits.value.split_first() {
+        Some((0, bytes)) => Ok(bytes),
+        _ => anyhow::bail!("unsupported bit string"),
+    }
+}
+
+/// The certificates that Authenticode signatures are required to chain to
+#[derive(Clone, Debug)]
+pub struct SignatureRoots {
+    /// The DER encoding of each certificate
+    certs: Vec<Vec<u8>>,
+}
+
+impl SignatureRoots {
+    /// Parses one or more PEM encoded certificates, any PEM documents that are
+    /// not certificates are ignored
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        const END: &str = "-----END ";
+
+        let mut certs = Vec::new();
+        let mut rest = pem;
+
+        while let Some(start) = rest.find("-----BEGIN ") {
+            let end = rest[start..]
+                .find(END)
+                .and_then(|end| {
+                    let end = start + end + END.len();
+                    rest[end..]
+                        .find("-----")
+                        .map(|label_end| end + label_end + 5)
+                })
+                .context("PEM document is not terminated")?;
+
+            let (label, der) = pem_rfc7468::decode_vec(&rest.as_bytes()[start..end])
+                .map_err(|err| anyhow::anyhow!("invalid PEM document: {}", err))?;
+            if label == "CERTIFICATE" {
+                Certificate::parse(Der(&der).expect(TAG_SEQUENCE)?)
+                    .context("invalid certificate")?;
+                certs.push(der);
+            }
+
+            rest = &rest[end..];
+        }
+
+        anyhow::ensure!(!certs.is_empty(), "no certificates were found");
+        Ok(Self { certs })
+    }
+}
+
+/// Defaults to the certificate that issues Microsoft's code signing
+/// certificates, "Microsoft Code Signing PCA 2011"
+impl Default for SignatureRoots {
+    fn default() -> Self {
+        Self::from_pem(MICROSOFT_CODE_SIGNING_PCA_2011).expect("the bundled certificate is valid")
+    }
+}
+
+/// The signer of a signature
+struct SignerInfo<'der> {
+    /// The issuer and serial number of the signing certificate
+    issuer: &'der [u8],
+    serial: &'der [u8],
+    digest_alg: DigestAlgorithm,
+    /// The signed attributes, which include the digest of the content
+    signed_attrs: Tlv<'der>,
+    signature: &'der [u8],
+}
+
+impl<'der> SignerInfo<'der> {
+    fn parse(signer: Tlv<'der>) -> Result<Self, Error> {
+        let mut fields = signer.contents();
+        fields.expect(TAG_INTEGER)?;
+
+        let mut sid = fields.expect(TAG_SEQUENCE)?.contents();
+        let issuer = sid.expect(TAG_SEQUENCE)?.raw;
+        let serial = sid.expect(TAG_INTEGER)?.value;
+
+        let digest_alg = DigestAlgorithm::from_identifier(fields.expect(TAG_SEQUENCE)?)?;
+        let signed_attrs = fields
+            .optional(TAG_CONTEXT_0)?
+            .context("signer doesn't have any signed attributes")?;
+
+        // The signature algorithm is either just RSA, with the digest algorithm
+        // specified above, or an RSA signature algorithm using the same digest
+        let signature_alg = fields.expect(TAG_SEQUENCE)?;
+        if signature_alg.contents().expect(TAG_OID)?.value != OID_RSA_ENCRYPTION {
+            anyhow::ensure!(
+                DigestAlgorithm::from_identifier(signature_alg)? == digest_alg,
+                "signature algorithm doesn't match the digest algorithm"
+            );
+        }
+
+        let signature = fields.expect(TAG_OCTET_STRING)?.value;
+
+        Ok(Self {
+            issuer,
+            serial,
+            digest_alg,
+            signed_attrs,
+            signature,
+        })
+    }
+}
+
+/// An Authenticode signature
+struct Signature<'der> {
+    /// The algorithm used to calculate the digest of the signed file
+    digest_alg: DigestAlgorithm,
+    /// The digest of the signed file
+    digest: &'der [u8],
+    /// The contents of the `SpcIndirectDataContent`, which are signed
+    content: &'der [u8],
+    certificates: Vec<Certificate<'der>>,
+    signer: SignerInfo<'der>,
+}
+
+impl<'der> Signature<'der> {
+    fn parse(der: &'der [u8]) -> Result<Self, Error> {
+        let mut content_info = Der(der).expect(TAG_SEQUENCE)?.contents();
+        anyhow::ensure!(
+            content_info.expect(TAG_OID)?.value == OID_SIGNED_DATA,
+            "signature is not PKCS#7 signed data"
+        );
+
+        let mut signed_data = content_info
+            .expect(TAG_CONTEXT_0)?
+            .contents()
+            .expect(TAG_SEQUENCE)?
+            .contents();
+        let _version = signed_data.expect(TAG_INTEGER)?;
+        let _digest_algs = signed_data.expect(TAG_SET)?;
+
+        let mut encap_content = signed_data.expect(TAG_SEQUENCE)?.contents();
+        anyhow::ensure!(
+            encap_content.expect(TAG_OID)?.value == OID_SPC_INDIRECT_DATA,
+            "signature is not an Authenticode signature"
+        );
+
+        // SpcIndirectDataContent ::= SEQUENCE {
+        //     data SpcAttributeTypeAndOptionalValue,
+        //     messageDigest DigestInfo
+        // }
+        let content = encap_content
+            .expect(TAG_CONTEXT_0)?
+            .contents()
+            .expect(TAG_SEQUENCE)?;
+        let mut indirect_data = content.contents();
+        let _data = indirect_data.expect(TAG_SEQUENCE)?;
+        let mut digest_info = indirect_data.expect(TAG_SEQUENCE)?.contents();
+        let digest_alg = DigestAlgorithm::from_identifier(digest_info.expect(TAG_SEQUENCE)?)?;
+        let digest = digest_info.expect(TAG_OCTET_STRING)?.value;
+
+        let mut certificates = Vec::new();
+        if let Some(certs) = signed_data.optional(TAG_CONTEXT_0)? {
+            let mut certs = certs.contents();
+            while !certs.is_empty() {
+                // Other certificate formats are ignored
+                if let Some(cert) = certs.optional(TAG_SEQUENCE)? {
+                    certificates.push(Certificate::parse(cert).context("invalid certificate")?);
+                } else {
+                    certs.next()?;
+                }
+            }
+        }
+        let _crls = signed_data.optional(TAG_CONTEXT_1)?;
+
+        let mut signers = signed_data.expect(TAG_SET)?.contents();
+        let signer = SignerInfo::parse(signers.expect(TAG_SEQUENCE)?).context("invalid signer")?;
+        anyhow::ensure!(
+            signers.is_empty(),
+            "signature has more than one signer, which Authenticode doesn't allow"
+        );
+
+        Ok(Self {
+            digest_alg,
+            digest,
+            content: content.value,
+            certificates,
+            signer,
+        })
+    }
+
+    /// Verifies that the signature is of the file with the specified digest,
+    /// and that it was signed by a certificate that chains to one of the roots
+    fn verify(&self, file_digest: &[u8], roots: &SignatureRoots) -> Result<(), Error> {
+        anyhow::ensure!(
+            self.digest == file_digest,
+            "the digest of the file doesn't match the digest in its signature"
+        );
+
+        let signer = &self.signer;
+        let signing_cert = self
+            .certificates
+            .iter()
+            .find(|cert| cert.issuer == signer.issuer && cert.serial == signer.serial)
+            .context("signature doesn't contain its signing certificate")?;
+
+        // The signed attributes must contain the content type, and the digest
+        // of the content, which is what actually ties the signature to the file
+        let mut content_type = None;
+        let mut message_digest = None;
+        let mut attributes = signer.signed_attrs.contents();
+        while !attributes.is_empty() {
+            let mut attribute = attributes.expect(TAG_SEQUENCE)?.contents();
+            let oid = attribute.expect(TAG_OID)?.value;
+            let mut values = attribute.expect(TAG_SET)?.contents();
+
+            match oid {
+                OID_CONTENT_TYPE => content_type = Some(values.expect(TAG_OID)?.value),
+                OID_MESSAGE_DIGEST => message_digest = Some(values.expect(TAG_OCTET_STRING)?.value),
+                _ => {}
+            }
+        }
+
+        anyhow::ensure!(
+            content_type == Some(OID_SPC_INDIRECT_DATA),
+            "signed content type is not an Authenticode signature"
+        );
+        anyhow::ensure!(
+            message_digest == Some(&*signer.digest_alg.digest(self.content)),
+            "signed digest doesn't match the signature contents"
+        );
+
+        // The signature is calculated over the signed attributes with the
+        // `SET OF` tag, rather than the implicit tag used in the `SignerInfo`
+        let mut signed_attrs = signer.signed_attrs.raw.to_vec();
+        signed_attrs[0] = TAG_SET;
+        verify_rsa(
+            signing_cert.public_key,
+            signer.digest_alg,
+            &signed_attrs,
+            signer.signature,
+        )
+        .with_context(|| format!("failed to verify signature by '{}'", signing_cert.name()))?;
+
+        anyhow::ensure!(
+            signing_cert.code_signing,
+            "'{}' is not allowed to sign code",
+            signing_cert.name()
+        );
+
+        self.verify_chain(signing_cert, roots)
+    }
+
+    /// Verifies that the certificate chains to one of the roots via the
+    /// certificates in the signature
+    fn verify_chain(
+        &self,
+        signing_cert: &Certificate<'_>,
+        roots: &SignatureRoots,
+    ) -> Result<(), Error> {
+        let roots = roots
+            .certs
+            .iter()
+            .map(|root| Certificate::parse(Der(root).expect(TAG_SEQUENCE)?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut cert = signing_cert;
+        for _ in 0..MAX_CHAIN_LEN {
+            for root in &roots {
+                if cert.raw == root.raw || cert.verify_issued_by(root).is_ok() {
+                    return Ok(());
+                }
+            }
+
+            cert = self
+                .certificates
+                .iter()
+                .find(|issuer| issuer.is_ca && cert.verify_issued_by(issuer).is_ok())
+                .with_context(|| {
+                    format!(
+                        "the certificate chain of '{}' doesn't lead to a trusted root, '{}' was issued by an unknown certificate",
+                        signing_cert.name(),
+                        cert.name(),
+                    )
+                })?;
+        }
+
+        anyhow::bail!(
+            "the certificate chain of '{}' is too long",
+            signing_cert.name()
+        )
+    }
+}
+
+/// Feeds the remaining contents of the reader to the hasher
+fn hash_reader(reader: &mut impl Read, hasher: &mut dyn DynDigest) -> io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(()),
+            read => hasher.update(&buf[..read]),
+        }
+    }
+}
+
+/// Calculates the digest of the streams and storages in an MSI storage. The
+/// children of each storage are hashed in the order of the UTF-16 encoding
+/// of their names, followed by the class id of the storage itself.
+fn hash_msi_storage<F: Read + Seek>(
+    msi: &mut cfb::CompoundFile<F>,
+    storage: &std::path::Path,
+    hasher: &mut dyn DynDigest,
+) -> Result<(), Error> {
+    let is_root = storage == std::path::Path::new("/");
+
+    let mut children: Vec<_> = msi
+        .read_storage(storage)?
+        .filter(|entry| {
+            !(is_root
+                && (entry.name() == DIGITAL_SIGNATURE || entry.name() == MSI_DIGITAL_SIGNATURE_EX))
+        })
+        .map(|entry| {
+            // Names are compared including their null terminator, so a name
+            // that is a prefix of another is sorted first
+            let key: Vec<u8> = entry
+                .name()
+                .encode_utf16()
+                .chain(Some(0))
+                .flat_map(u16::to_le_bytes)
+                .collect();
+            (key, entry.path().to_owned(), entry.is_stream())
+        })
+        .collect();
+    children.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (_, path, is_stream) in children {
+        if is_stream {
+            hash_reader(&mut msi.open_stream(&path)?, hasher)?;
+        } else {
+            hash_msi_storage(msi, &path, hasher)?;
+        }
+    }
+
+    let entry = msi.entry(storage)?;
+    let (d1, d2, d3, d4) = entry.clsid().as_fields();
+    hasher.update(&d1.to_le_bytes());
+    hasher.update(&d2.to_le_bytes());
+    hasher.update(&d3.to_le_bytes());
+    hasher.update(d4);
+
+    Ok(())
+}
+
+/// Calculates the digest of an MSI, ie. of every stream and storage other
+/// than the signature
+fn hash_msi<F: Read + Seek>(
+    msi: &mut cfb::CompoundFile<F>,
+    hasher: &mut dyn DynDigest,
+) -> Result<(), Error> {
+    // The digest of the metadata is included as is rather than recalculated,
+    // the contents of every stream are still part of the digest below
+    if msi.is_stream(MSI_DIGITAL_SIGNATURE_EX) {
+        hash_reader(&mut msi.open_stream(MSI_DIGITAL_SIGNATURE_EX)?, hasher)?;
+    }
+
+    hash_msi_storage(msi, std::path::Path::new("/"), hasher)
+}
+
+/// Verifies the Authenticode signature of an MSI, which is stored in the
+/// `\u{5}DigitalSignature` stream
+pub(crate) fn verify_msi(path: &Path, roots: &SignatureRoots) -> Result<(), Error> {
+    let mut msi = std::fs::File::open(path)
+        .and_then(|file| cfb::CompoundFile::open(io::BufReader::new(file)))
+        .with_context(|| format!("unable to open MSI {}", path))?;
+
+    let mut der = Vec::new();
+    msi.open_stream(DIGITAL_SIGNATURE)
+        .and_then(|mut stream| stream.read_to_end(&mut der))
+        .context("MSI is not signed")?;
+    let signature = Signature::parse(&der).context("invalid signature")?;
+
+    let mut hasher = signature.digest_alg.hasher();
+    hash_msi(&mut msi, &mut *hasher)?;
+
+    signature.verify(&hasher.finalize(), roots)
+}
+
+/// The header of a signed CAB, the fixed header followed by the sizes of the
+/// reserved areas, and the 20 byte header reserve that holds the location of
+/// the signature
+struct SignedCabHeader([u8; 60]);
+
+impl SignedCabHeader {
+    fn read(cab: &mut impl Read) -> Result<Self, Error> {
+        use crate::cabinet::{FLAG_RESERVE_PRESENT, SIGNATURE};
+
+        let mut header = Self([0; 60]);
+        cab.read_exact(&mut header.0).context("CAB is not signed")?;
+        anyhow::ensure!(&header.0[..4] == SIGNATURE, "not a cabinet file");
+        anyhow::ensure!(
+            header.flags() & FLAG_RESERVE_PRESENT != 0
+                && header.u16_at(36) == 20
+                && header.u32_at(40) == 0x0010_0000,
+            "CAB is not signed"
+        );
+        anyhow::ensure!(
+            header.0[38] == 0,
+            "signed CABs with a per folder reserved area are not supported"
+        );
+
+        Ok(header)
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes([
+            self.0[offset],
+            self.0[offset + 1],
+            self.0[offset + 2],
+            self.0[offset + 3],
+        ])
+    }
+
+    fn flags(&self) -> u16 {
+        self.u16_at(30)
+    }
+
+    fn signature_offset(&self) -> u64 {
+        self.u32_at(44).into()
+    }
+
+    fn signature_len(&self) -> usize {
+        self.u32_at(48) as usize
+    }
+}
+
+/// Calculates the digest of a signed CAB, ie. everything up to the signature
+/// other than the fields in the header that are changed by signing
+fn hash_cab<R: Read + Seek>(cab: &mut R, hasher: &mut dyn DynDigest) -> Result<(), Error> {
+    use crate::cabinet::{FLAG_NEXT_CABINET, FLAG_PREV_CABINET};
+
+    cab.seek(SeekFrom::Start(0))?;
+    let header = SignedCabHeader::read(cab)?;
+
+    // The header is hashed without the reserved fields, the index of the
+    // cabinet in its set, the reserved area sizes, and the location of the
+    // signature
+    for range in [
+        0..4,
+        8..16,
+        16..20,
+        20..26,
+        26..28,
+        28..30,
+        30..32,
+        32..34,
+        56..60,
+    ] {
+        hasher.update(&header.0[range]);
+    }
+
+    let mut offset = header.0.len() as u64;
+
+    // The names of the previous and next cabinets and disks in the set,
+    // including their null terminators
+    let mut num_strings = 0;
+    if header.flags() & FLAG_PREV_CABINET != 0 {
+        num_strings += 2;
+    }
+    if header.flags() & FLAG_NEXT_CABINET != 0 {
+        num_strings += 2;
+    }
+    for _ in 0..num_strings {
+        loop {
+            let mut byte = [0; 1];
+            cab.read_exact(&mut byte)?;
+            hasher.update(&byte);
+            offset += 1;
+            if byte[0] == 0 {
+                break;
+            }
+        }
+    }
+
+    // Each CFFOLDER entry is 8 bytes
+    let mut folders = vec![0; usize::from(header.u16_at(26)) * 8];
+    cab.read_exact(&mut folders)?;
+    hasher.update(&folders);
+    offset += folders.len() as u64;
+
+    let first_file = u64::from(header.u32_at(16));
+    let signature_offset = header.signature_offset();
+    anyhow::ensure!(
+        offset == first_file && first_file <= signature_offset,
+        "CAB headers are corrupt"
+    );
+
+    // The rest of the CAB, up to the signature
+    hash_reader(&mut cab.take(signature_offset - offset), hasher)?;
+    Ok(())
+}
+
+/// Verifies the Authenticode signature of a CAB, which is appended to the
+/// end of the CAB, with its location stored in the reserved area of the header
+pub(crate) fn verify_cab(path: &Path, roots: &SignatureRoots) -> Result<(), Error> {
+    let mut cab = std::fs::File::open(path)
+        .map(io::BufReader::new)
+        .with_context(|| format!("unable to open CAB {}", path))?;
+
+    let header = SignedCabHeader::read(&mut cab)?;
+    let mut der = vec![0; header.signature_len()];
+    cab.seek(SeekFrom::Start(header.signature_offset()))
+        .and_then(|_| cab.read_exact(&mut der))
+        .context("CAB signature is truncated")?;
+    let signature = Signature::parse(&der).context("invalid signature")?;
+
+    let mut hasher = signature.digest_alg.hasher();
+    hash_cab(&mut cab, &mut *hasher)?;
+
+    signature.verify(&hasher.finalize(), roots)
+}
+
+/// Verifies the signatures of a downloaded MSI and each of its CABs. VSIX
+/// packages don't use Authenticode, so they are only validated against the
+/// checksum in the package manifest like every other payload.
+pub(crate) fn verify(
+    item: &crate::WorkItem,
+    contents: &crate::download::PayloadContents,
+    roots: &SignatureRoots,
+) -> Result<(), Error> {
+    let (msi, cabs) = match contents {
+        crate::download::PayloadContents::Vsix(_) => return Ok(()),
+        crate::download::PayloadContents::Msi { msi, cabs } => (msi, cabs),
+    };
+
+    item.progress.set_message("🔏 verifying...");
+
+    verify_msi(msi, roots).with_context(|| {
+        format!(
+            "failed to verify the signature of {}",
+            item.payload.filename
+        )
+    })?;
+
+    for cab in cabs {
+        verify_cab(&cab.content, roots)
+            .with_context(|| format!("failed to verify the signature of {}", cab.path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_ROOT: &str = include_str!("../tests/authenticode/root.pem");
+    const TEST_SIGNER: &str = include_str!("../tests/authenticode/signer.pem");
+    const TEST_SIGNER_KEY: &str = include_str!("../tests/authenticode/signer.key");
+    /// The signature of `WebView2Loader.dll`, which is signed by Microsoft
+    const MICROSOFT_SIGNATURE: &[u8] = include_bytes!("../tests/authenticode/WebView2Loader.p7s");
+
+    /// 2.16.840.1.101.3.4.2.1
+    const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+    /// 1.3.6.1.4.1.311.2.1.30, ie. `SpcSipInfo`, which is what MSIs and CABs use
+    const OID_SPC_SIP_INFO: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x1e];
+
+    fn tlv(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let value = parts.concat();
+
+        let mut der = vec![tag];
+        if value.len() < 0x80 {
+            der.push(value.len() as u8);
+        } else {
+            let len = (value.len() as u32).to_be_bytes();
+            let skip = len.iter().take_while(|b| **b == 0).count();
+            der.push(0x80 | (4 - skip) as u8);
+            der.extend_from_slice(&len[skip..]);
+        }
+        der.extend(value);
+        der
+    }
+
+    fn pem_to_der(pem: &str) -> Vec<u8> {
+        pem_rfc7468::decode_vec(pem.as_bytes()).unwrap().1
+    }
+
+    /// Creates an Authenticode signature of a file with the specified sha256
+    /// digest, signed by the test signer
+    fn sign(digest: &[u8]) -> Vec<u8> {
+        use rsa::pkcs8::DecodePrivateKey;
+        use sha2::Digest;
+
+        let sha256 = tlv(TAG_SEQUENCE, &[&tlv(TAG_OID, &[OID_SHA256]), &[0x05, 0x00]]);
+        let content = tlv(
+            TAG_SEQUENCE,
+            &[
+                &tlv(TAG_SEQUENCE, &[&tlv(TAG_OID, &[OID_SPC_SIP_INFO])]),
+                &tlv(TAG_SEQUENCE, &[&sha256, &tlv(TAG_OCTET_STRING, &[digest])]),
+            ],
+        );
+        let content_digest = sha2::Sha256::digest(Der(&content).next().unwrap().value);
+
+        let mut signed_attrs = tlv(
+            TAG_SET,
+            &[
+                &tlv(
+                    TAG_SEQUENCE,
+                    &[
+                        &tlv(TAG_OID, &[OID_CONTENT_TYPE]),
+                        &tlv(TAG_SET, &[&tlv(TAG_OID, &[OID_SPC_INDIRECT_DATA])]),
+                    ],
+                ),
+                &tlv(
+                    TAG_SEQUENCE,
+                    &[
+                        &tlv(TAG_OID, &[OID_MESSAGE_DIGEST]),
+                        &tlv(TAG_SET, &[&tlv(TAG_OCTET_STRING, &[&content_digest])]),
+                    ],
+                ),
+            ],
+        );
+
+        let key = rsa::RsaPrivateKey::from_pkcs8_der(&pem_to_der(TEST_SIGNER_KEY)).unwrap();
+        let signature = key
+            .sign(
+                rsa::Pkcs1v15Sign::new::<sha2::Sha256>(),
+                &sha2::Sha256::digest(&signed_attrs),
+            )
+            .unwrap();
+        signed_attrs[0] = TAG_CONTEXT_0;
+
+        let signer = pem_to_der(TEST_SIGNER);
+        let signer_cert = Certificate::parse(Der(&signer).next().unwrap()).unwrap();
+
+        let signer_info = tlv(
+            TAG_SEQUENCE,
+            &[
+                &tlv(TAG_INTEGER, &[&[1]]),
+                &tlv(
+                    TAG_SEQUENCE,
+                    &[signer_cert.issuer, &tlv(TAG_INTEGER, &[signer_cert.serial])],
+                ),
+                &sha256,
+                &signed_attrs,
+                &tlv(
+                    TAG_SEQUENCE,
+                    &[&tlv(TAG_OID, &[OID_RSA_ENCRYPTION]), &[0x05, 0x00]],
+                ),
+                &tlv(TAG_OCTET_STRING, &[&signature]),
+            ],
+        );
+
+        let signed_data = tlv(
+            TAG_SEQUENCE,
+            &[
+                &tlv(TAG_INTEGER, &[&[1]]),
+                &tlv(TAG_SET, &[&sha256]),
+                &tlv(
+                    TAG_SEQUENCE,
+                    &[
+                        &tlv(TAG_OID, &[OID_SPC_INDIRECT_DATA]),
+                        &tlv(TAG_CONTEXT_0, &[&content]),
+                    ],
+                ),
+                &tlv(TAG_CONTEXT_0, &[&signer]),
+                &tlv(TAG_SET, &[&signer_info]),
+            ],
+        );
+
+        tlv(
+            TAG_SEQUENCE,
+            &[
+                &tlv(TAG_OID, &[OID_SIGNED_DATA]),
+                &tlv(TAG_CONTEXT_0, &[&signed_data]),
+            ],
+        )
+    }
+
+    fn test_roots() -> SignatureRoots {
+        SignatureRoots::from_pem(TEST_ROOT).unwrap()
+    }
+
+    fn write(dir: &tempfile::TempDir, name: &str, contents: &[u8]) -> crate::PathBuf {
+        let path = crate::PathBuf::from_path_buf(dir.path().join(name)).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn microsoft_signature() {
+        let signature = Signature::parse(MICROSOFT_SIGNATURE).unwrap();
+        assert_eq!(signature.digest_alg, DigestAlgorithm::Sha256);
+
+        // The signature of the DLL itself isn't checked, just that the
+        // signature chains to the bundled certificate
+        signature
+            .verify(signature.digest, &SignatureRoots::default())
+            .unwrap();
+
+        let err = signature
+            .verify(signature.digest, &test_roots())
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("doesn't lead to a trusted root"),
+            "{:#}",
+            err
+        );
+
+        let err = signature
+            .verify(&[0; 32], &SignatureRoots::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("digest of the file doesn't match"));
+
+        // Changing the digest of the file in the signature invalidates it
+        let mut tampered = MICROSOFT_SIGNATURE.to_vec();
+        let digest_offset = tampered
+            .windows(signature.digest.len())
+            .position(|window| window == signature.digest)
+            .unwrap();
+        tampered[digest_offset] ^= 0xff;
+
+        let signature = Signature::parse(&tampered).unwrap();
+        let err = signature
+            .verify(signature.digest, &SignatureRoots::default())
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("signed digest doesn't match the signature contents"));
+    }
+
+    #[test]
+    fn roots() {
+        let roots = SignatureRoots::from_pem(&format!("a comment\n{}\n{}", TEST_ROOT, TEST_SIGNER))
+            .unwrap();
+        assert_eq!(roots.certs.len(), 2);
+        assert_eq!(roots.certs[0], pem_to_der(TEST_ROOT));
+
+        // Private keys are ignored
+        assert_eq!(
+            SignatureRoots::from_pem(&format!("{}{}", TEST_SIGNER_KEY, TEST_ROOT))
+                .unwrap()
+                .certs
+                .len(),
+            1
+        );
+
+        let err = SignatureRoots::from_pem(TEST_SIGNER_KEY).unwrap_err();
+        assert_eq!(err.to_string(), "no certificates were found");
+    }
+
+    #[test]
+    fn signed_cab() {
+        use sha2::Digest;
+        use std::convert::TryInto;
+
+        let mut builder = cab::CabinetBuilder::new();
+        builder
+            .add_folder(cab::CompressionType::MsZip)
+            .add_file("a.h");
+        let mut writer = builder.build(io::Cursor::new(Vec::new())).unwrap();
+        while let Some(mut fw) = writer.next_file().unwrap() {
+            io::Write::write_all(&mut fw, &b"#pragma once\n".repeat(100)).unwrap();
+        }
+        let unsigned = writer.finish().unwrap().into_inner();
+
+        let td = tempfile::TempDir::new().unwrap();
+        let err = verify_cab(&write(&td, "unsigned.cab", &unsigned), &test_roots()).unwrap_err();
+        assert_eq!(err.to_string(), "CAB is not signed");
+
+        // Insert the header reserve, which moves everything after the header
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(unsigned[offset..offset + 4].try_into().unwrap());
+        let put_u32 = |cab: &mut Vec<u8>, offset: usize, val: u32| {
+            cab[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+        };
+
+        let mut cab = unsigned[..36].to_vec();
+        cab.extend_from_slice(&[20, 0, 0, 0]);
+        cab.extend_from_slice(&[0, 0, 0x10, 0]);
+        cab.extend_from_slice(&(unsigned.len() as u32 + 24).to_le_bytes());
+        cab.extend_from_slice(&[0; 12]);
+        cab.extend_from_slice(&unsigned[36..]);
+        cab[30] |= crate::cabinet::FLAG_RESERVE_PRESENT as u8;
+        put_u32(&mut cab, 8, u32_at(8) + 24);
+        put_u32(&mut cab, 16, u32_at(16) + 24);
+        // The first data block of the only folder
+        put_u32(&mut cab, 60, u32_at(36) + 24);
+
+        let mut hasher = sha2::Sha256::new();
+        hash_cab(&mut io::Cursor::new(&cab), &mut hasher).unwrap();
+        let signature = sign(&hasher.finalize());
+        put_u32(&mut cab, 48, signature.len() as u32);
+
+        let mut signed = cab.clone();
+        signed.extend_from_slice(&signature);
+
+        let path = write(&td, "signed.cab", &signed);
+        verify_cab(&path, &test_roots()).unwrap();
+
+        let err = verify_cab(&path, &SignatureRoots::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("doesn't lead to a trusted root"));
+
+        // The signed CAB can still be unpacked
+        let cabinet = crate::cabinet::Cabinet::read(&mut io::Cursor::new(&signed)).unwrap();
+        let mut contents = Vec::new();
+        cabinet
+            .folder_reader(io::Cursor::new(&signed), 0)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"#pragma once\n".repeat(100));
+
+        // Changing any of the data invalidates the signature
+        let mut tampered = signed.clone();
+        let last = cab.len() - 1;
+        tampered[last] ^= 0xff;
+        let err = verify_cab(&write(&td, "tampered.cab", &tampered), &test_roots()).unwrap_err();
+        assert!(err.to_string().contains("digest of the file doesn't match"));
+    }
+
+    #[test]
+    fn signed_msi() {
+        use sha2::Digest;
+
+        let mut msi =
+            msi::Package::create(msi::PackageType::Installer, io::Cursor::new(Vec::new())).unwrap();
+        msi.set_database_codepage(msi::CodePage::Utf8);
+        let unsigned = msi.into_inner().unwrap().into_inner();
+
+        let td = tempfile::TempDir::new().unwrap();
+        let err = verify_msi(&write(&td, "unsigned.msi", &unsigned), &test_roots()).unwrap_err();
+        assert_eq!(err.to_string(), "MSI is not signed");
+
+        let mut msi = cfb::CompoundFile::open(io::Cursor::new(unsigned)).unwrap();
+        let mut hasher = sha2::Sha256::new();
+        hash_msi(&mut msi, &mut hasher).unwrap();
+        io::Write::write_all(
+            &mut msi.create_stream(DIGITAL_SIGNATURE).unwrap(),
+            &sign(&hasher.finalize()),
+        )
+        .unwrap();
+        let signed = msi.into_inner().into_inner();
+
+        let path = write(&td, "signed.msi", &signed);
+        verify_msi(&path, &test_roots()).unwrap();
+
+        let err = verify_msi(&path, &SignatureRoots::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("doesn't lead to a trusted root"));
+
+        // The signed MSI can still be read
+        msi::Package::open(io::Cursor::new(&signed)).unwrap();
+
+        // Adding a stream invalidates the signature
+        let mut msi = cfb::CompoundFile::open(io::Cursor::new(signed)).unwrap();
+        io::Write::write_all(&mut msi.create_stream("extra").unwrap(), b"extra").unwrap();
+        let tampered = msi.into_inner().into_inner();
+
+        let err = verify_msi(&write(&td, "tampered.msi", &tampered), &test_roots()).unwrap_err();
+        assert!(err.to_string().contains("digest of the file doesn't match"));
+    }
+
+    /// Checks the signature of every MSI and CAB in `XWIN_TEST_CABS`, eg.
+    /// `.xwin-cache/dl/sha256` after a download, against the bundled
+    /// certificate, as real payloads can't be checked in
+    #[test]
+    #[ignore]
+    fn real_signatures() {
+        let dir = std::env::var("XWIN_TEST_CABS").expect("XWIN_TEST_CABS is not set");
+        let mut checked = 0;
+
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = crate::PathBuf::from_path_buf(entry.unwrap().path()).unwrap();
+            if !path.is_file() {
+                continue;
+            }
+
+            let mut magic = [0; 4];
+            std::fs::File::open(&path)
+                .unwrap()
+                .read_exact(&mut magic)
+                .unwrap();
+
+            let res = match &magic {
+                crate::cabinet::SIGNATURE => verify_cab(&path, &SignatureRoots::default()),
+                [0xd0, 0xcf, 0x11, 0xe0] => verify_msi(&path, &SignatureRoots::default()),
+                _ => continue,
+            };
+            res.unwrap_or_else(|err| panic!("{}: {:#}", path, err));
+
+            checked += 1;
+        }
+
+        assert!(checked > 0, "no MSIs or CABs were found");
+    }
+}
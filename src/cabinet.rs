@@ -0,0 +1,746 @@
+//! A minimal CAB reader that decompresses each folder as a single stream.
+//!
+//! The data in a CAB folder is compressed as one continuous stream, so reading
+//! a file from the middle of a folder requires decompressing everything before
+//! it. The `cab` crate only allows reading individual files, each of which
+//! starts decompressing from the beginning of its folder, which makes
+//! extracting every file from a large folder quadratic. Instead, we read the
+//! headers ourselves and decompress each folder exactly once, extracting the
+//! files in the order they appear in the stream.
+//!
+//! See <https://docs.microsoft.com/en-us/previous-versions/bb417343(v=msdn.10)>
+
+use crate::Error;
+use anyhow::Context as _;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// "MSCF"
+pub(crate) const SIGNATURE: &[u8; 4] = b"MSCF";
+
+pub(crate) const FLAG_PREV_CABINET: u16 = 0x1;
+pub(crate) const FLAG_NEXT_CABINET: u16 = 0x2;
+pub(crate) const FLAG_RESERVE_PRESENT: u16 = 0x4;
+
+/// The first of the special folder indices used by files that are split
+/// across cabinets in a set
+const IFOLD_CONTINUED_FROM_PREV: u16 = 0xfffd;
+
+/// The maximum size of the MSZIP dictionary, ie. the deflate window
+const MSZIP_MAX_DICT: usize = 32 * 1024;
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+/// Skips a null terminated string, used for the names of the previous and
+/// next cabinets in a set, which we don't care about
+fn skip_string(r: &mut impl Read) -> io::Result<()> {
+    while read_u8(r)? != 0 {}
+    Ok(())
+}
+
+#[derive(Copy, Clone)]
+enum Compression {
+    None,
+    MsZip,
+    Lzx(lzxd::WindowSize),
+}
+
+impl Compression {
+    fn from_bits(bits: u16) -> Result<Self, Error> {
+        Ok(match bits & 0xf {
+            0 => Self::None,
+            1 => Self::MsZip,
+            3 => Self::Lzx(match (bits >> 8) & 0x1f {
+                15 => lzxd::WindowSize::KB32,
+                16 => lzxd::WindowSize::KB64,
+                17 => lzxd::WindowSize::KB128,
+                18 => lzxd::WindowSize::KB256,
+                19 => lzxd::WindowSize::KB512,
+                20 => lzxd::WindowSize::MB1,
+                21 => lzxd::WindowSize::MB2,
+                other => anyhow::bail!("invalid LZX window size {}", other),
+            }),
+            2 => anyhow::bail!("Quantum compression is not supported"),
+            other => anyhow::bail!("unknown compression type {}", other),
+        })
+    }
+}
+
+/// A file in a folder
+#[derive(Debug)]
+pub(crate) struct FolderFile {
+    pub(crate) name: String,
+    /// The offset of the file in the uncompressed folder stream
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+pub(crate) struct Folder {
+    /// The offset of the first data block in the CAB
+    first_block: u64,
+    num_blocks: u16,
+    compression: Compression,
+    /// The files in the folder, sorted by their offset in the folder stream
+    pub(crate) files: Vec<FolderFile>,
+}
+
+pub(crate) struct Cabinet {
+    pub(crate) folders: Vec<Folder>,
+    /// The size of the per data block reserved area
+    data_reserve: u8,
+}
+
+impl Cabinet {
+    /// Reads the headers of a CAB
+    pub(crate) fn read<R: Read + Seek>(r: &mut R) -> Result<Self, Error> {
+        let mut signature = [0; 4];
+        r.read_exact(&mut signature)?;
+        anyhow::ensure!(&signature == SIGNATURE, "not a cabinet file");
+
+        let _reserved1 = read_u32(r)?;
+        let _cabinet_size = read_u32(r)?;
+        let _reserved2 = read_u32(r)?;
+        let first_file = read_u32(r)?;
+        let _reserved3 = read_u32(r)?;
+        let _version = read_u16(r)?;
+        let num_folders = read_u16(r)?;
+        let num_files = read_u16(r)?;
+        let flags = read_u16(r)?;
+        let _set_id = read_u16(r)?;
+        let _set_index = read_u16(r)?;
+
+        let (folder_reserve, data_reserve) = if flags & FLAG_RESERVE_PRESENT != 0 {
+            let header_reserve = read_u16(r)?;
+            let folder_reserve = read_u8(r)?;
+            let data_reserve = read_u8(r)?;
+            r.seek(SeekFrom::Current(header_reserve.into()))?;
+            (folder_reserve, data_reserve)
+        } else {
+            (0, 0)
+        };
+
+        if flags & FLAG_PREV_CABINET != 0 {
+            skip_string(r)?;
+            skip_string(r)?;
+        }
+
+        if flags & FLAG_NEXT_CABINET != 0 {
+            skip_string(r)?;
+            skip_string(r)?;
+        }
+
+        let mut folders = (0..num_folders)
+            .map(|_| -> Result<_, Error> {
+                let first_block = read_u32(r)?.into();
+                let num_blocks = read_u16(r)?;
+                let compression = Compression::from_bits(read_u16(r)?)?;
+                r.seek(SeekFrom::Current(folder_reserve.into()))?;
+
+                Ok(Folder {
+                    first_block,
+                    num_blocks,
+                    compression,
+                    files: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        r.seek(SeekFrom::Start(first_file.into()))?;
+
+        for _ in 0..num_files {
+            let size = read_u32(r)?.into();
+            let offset = read_u32(r)?.into();
+            let folder = read_u16(r)?;
+            let _date = read_u16(r)?;
+            let _time = read_u16(r)?;
+            let _attributes = read_u16(r)?;
+
+            let mut name = Vec::new();
+            loop {
+                match read_u8(r)? {
+                    0 => break,
+                    c => name.push(c),
+                }
+            }
+            let name = String::from_utf8(name).context("file name is not utf-8")?;
+
+            // Files that are continued from a previous cabinet, or continued
+            // in the next one, use special indices
+            anyhow::ensure!(
+                folder < IFOLD_CONTINUED_FROM_PREV,
+                "'{}' is split across multiple cabinets, which is not supported",
+                name
+            );
+
+            let folder = folders
+                .get_mut(folder as usize)
+                .with_context(|| format!("'{}' has an invalid folder index {}", name, folder))?;
+
+            folder.files.push(FolderFile { name, offset, size });
+        }
+
+        for folder in &mut folders {
+            folder.files.sort_by_key(|file| file.offset);
+        }
+
+        Ok(Self {
+            folders,
+            data_reserve,
+        })
+    }
+
+    /// Creates a reader over the uncompressed stream of the specified folder
+    pub(crate) fn folder_reader<R: Read + Seek>(
+        &self,
+        mut r: R,
+        index: usize,
+    ) -> Result<FolderReader<R>, Error> {
+        let folder = &self.folders[index];
+        r.seek(SeekFrom::Start(folder.first_block))?;
+
+        Ok(FolderReader {
+            r,
+            data_reserve: self.data_reserve,
+            remaining_blocks: folder.num_blocks,
+            block_index: 0,
+            decompressor: match folder.compression {
+                Compression::None => Decompressor::None,
+                Compression::MsZip => Decompressor::MsZip {
+                    inflate: Box::new(flate2::Decompress::new(false)),
+                    dictionary: Vec::new(),
+                },
+                Compression::Lzx(window) => Decompressor::Lzx(Box::new(lzxd::Lzxd::new(window))),
+            },
+            compressed: Vec::new(),
+            block: Vec::new(),
+            block_pos: 0,
+        })
+    }
+}
+
+enum Decompressor {
+    None,
+    MsZip {
+        inflate: Box<flate2::Decompress>,
+        /// The uncompressed contents of the previous block
+        dictionary: Vec<u8>,
+    },
+    Lzx(Box<lzxd::Lzxd>),
+}
+
+/// The data checksum used in CABs
+fn checksum(data: &[u8], mut seed: u32) -> u32 {
+    let mut chunks = data.chunks_exact(4);
+
+    for chunk in &mut chunks {
+        seed ^= u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    // The remaining bytes are treated as a big endian value
+    let rem = chunks
+        .remainder()
+        .iter()
+        .fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+
+    seed ^ rem
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads the uncompressed stream of a folder, decompressing each data block
+/// exactly once
+pub(crate) struct FolderReader<R> {
+    r: R,
+    data_reserve: u8,
+    remaining_blocks: u16,
+    block_index: u32,
+    decompressor: Decompressor,
+    compressed: Vec<u8>,
+    /// The uncompressed contents of the current block
+    block: Vec<u8>,
+    block_pos: usize,
+}
+
+impl<R: Read> FolderReader<R> {
+    /// Reads and decompresses the next data block, returning false if there
+    /// are no more blocks
+    fn next_block(&mut self) -> io::Result<bool> {
+        if self.remaining_blocks == 0 {
+            return Ok(false);
+        }
+
+        let expected_checksum = read_u32(&mut self.r)?;
+        let compressed_size = read_u16(&mut self.r)?;
+        let uncompressed_size = read_u16(&mut self.r)?;
+
+        // A block that is continued in the next cabinet has no uncompressed size
+        if uncompressed_size == 0 {
+            return Err(invalid_data(format!(
+                "data block {} is split across multiple cabinets, which is not supported",
+                self.block_index
+            )));
+        }
+
+        // The checksum covers the reserved area, the data, and the sizes
+        self.compressed.resize(
+            usize::from(self.data_reserve) + usize::from(compressed_size),
+            0,
+        );
+        self.r.read_exact(&mut self.compressed)?;
+
+        if expected_checksum != 0 {
+            let actual = checksum(&self.compressed, 0)
+                ^ (u32::from(compressed_size) | (u32::from(uncompressed_size) << 16));
+
+            if actual != expected_checksum {
+                return Err(invalid_data(format!(
+                    "checksum mismatch in data block {}, expected {:08x} != actual {:08x}",
+                    self.block_index, expected_checksum, actual
+                )));
+            }
+        }
+
+        let data = &self.compressed[usize::from(self.data_reserve)..];
+        let uncompressed_size = usize::from(uncompressed_size);
+        let block_index = self.block_index;
+
+        self.block.clear();
+
+        match &mut self.decompressor {
+            Decompressor::None => self.block.extend_from_slice(data),
+            Decompressor::MsZip {
+                inflate,
+                dictionary,
+            } => {
+                let data = data.strip_prefix(b"CK").ok_or_else(|| {
+                    invalid_data(format!(
+                        "invalid MSZIP signature in data block {}",
+                        block_index
+                    ))
+                })?;
+
+                // Each block is a separate deflate stream, but which can
+                // reference the contents of the previous block, so we prime
+                // the window by inflating the previous block as a stored block
+                inflate.reset(false);
+
+                if !dictionary.is_empty() {
+                    let len = dictionary.len() as u16;
+                    let mut stored = Vec::with_capacity(dictionary.len() + 5);
+                    stored.push(0);
+                    stored.extend_from_slice(&len.to_le_bytes());
+                    stored.extend_from_slice(&(!len).to_le_bytes());
+                    stored.extend_from_slice(dictionary);
+
+                    let mut discard = Vec::with_capacity(dictionary.len());
+                    inflate
+                        .decompress_vec(&stored, &mut discard, flate2::FlushDecompress::Sync)
+                        .map_err(|err| invalid_data(format!("MSZIP error: {}", err)))?;
+                }
+
+                self.block.reserve(uncompressed_size);
+                inflate
+                    .decompress_vec(data, &mut self.block, flate2::FlushDecompress::Finish)
+                    .map_err(|err| {
+                        invalid_data(format!(
+                            "MSZIP error in data block {}: {}",
+                            block_index, err
+                        ))
+                    })?;
+
+                dictionary.clear();
+                dictionary.extend_from_slice(
+                    &self.block[self.block.len().saturating_sub(MSZIP_MAX_DICT)..],
+                );
+            }
+            Decompressor::Lzx(lzx) => {
+                let decompressed = lzx.decompress_next(data).map_err(|err| {
+                    invalid_data(format!("LZX error in data block {}: {}", block_index, err))
+                })?;
+
+                self.block
+                    .extend_from_slice(&decompressed[..uncompressed_size.min(decompressed.len())]);
+            }
+        }
+
+        if self.block.len() != uncompressed_size {
+            return Err(invalid_data(format!(
+                "data block {} decompressed to {} bytes, expected {}",
+                self.block_index,
+                self.block.len(),
+                uncompressed_size
+            )));
+        }
+
+        self.remaining_blocks -= 1;
+        self.block_index += 1;
+        self.block_pos = 0;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for FolderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.block_pos == self.block.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.block.len() - self.block_pos);
+        buf[..len].copy_from_slice(&self.block[self.block_pos..self.block_pos + len]);
+        self.block_pos += len;
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn folders() {
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("a.h", b"#pragma once\n".repeat(1000)),
+            ("empty.txt", Vec::new()),
+            (
+                "b.lib",
+                (0..100_000u32)
+                    .flat_map(|i| (i % 251).to_le_bytes())
+                    .collect(),
+            ),
+        ];
+
+        let mut builder = cab::CabinetBuilder::new();
+        for ctype in [cab::CompressionType::None, cab::CompressionType::MsZip] {
+            let folder = builder.add_folder(ctype);
+            for (name, _) in &files {
+                folder.add_file(*name);
+            }
+        }
+
+        let mut writer = builder.build(io::Cursor::new(Vec::new())).unwrap();
+        while let Some(mut fw) = writer.next_file().unwrap() {
+            let contents = &files
+                .iter()
+                .find(|(name, _)| *name == fw.file_name())
+                .unwrap()
+                .1;
+            io::Write::write_all(&mut fw, contents).unwrap();
+        }
+        let cab = writer.finish().unwrap().into_inner();
+
+        let mut cursor = io::Cursor::new(&cab);
+        let cabinet = Cabinet::read(&mut cursor).unwrap();
+        assert_eq!(cabinet.folders.len(), 2);
+
+        for (i, folder) in cabinet.folders.iter().enumerate() {
+            let mut reader = cabinet.folder_reader(io::Cursor::new(&cab), i).unwrap();
+            let mut stream = Vec::new();
+            reader.read_to_end(&mut stream).unwrap();
+
+            for ((name, contents), file) in files.iter().zip(&folder.files) {
+                assert_eq!(*name, file.name);
+                let start = file.offset as usize;
+                assert_eq!(&stream[start..start + file.size as usize], &contents[..]);
+            }
+        }
+    }
+
+    /// Builds a cabinet with a single folder from already compressed data
+    /// blocks, as the `cab` crate can't create LZX folders, nor MSZIP blocks
+    /// that reference the previous block
+    fn build_cab(
+        compression: u16,
+        blocks: &[(Vec<u8>, usize)],
+        files: &[(&str, u32)],
+        folder_index: u16,
+    ) -> Vec<u8> {
+        let first_file = 36 + 8;
+        let first_block = first_file
+            + files
+                .iter()
+                .map(|(name, _)| 16 + name.len() + 1)
+                .sum::<usize>();
+
+        let mut cab = Vec::new();
+        cab.extend_from_slice(SIGNATURE);
+        cab.extend_from_slice(&0u32.to_le_bytes());
+        cab.extend_from_slice(&0u32.to_le_bytes()); // cabinet size, unused
+        cab.extend_from_slice(&0u32.to_le_bytes());
+        cab.extend_from_slice(&(first_file as u32).to_le_bytes());
+        cab.extend_from_slice(&0u32.to_le_bytes());
+        cab.extend_from_slice(&0x0103u16.to_le_bytes());
+        cab.extend_from_slice(&1u16.to_le_bytes());
+        cab.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        cab.extend_from_slice(&0u16.to_le_bytes()); // flags
+        cab.extend_from_slice(&0u16.to_le_bytes());
+        cab.extend_from_slice(&0u16.to_le_bytes());
+
+        cab.extend_from_slice(&(first_block as u32).to_le_bytes());
+        cab.extend_from_slice(&(blocks.len() as u16).to_le_bytes());
+        cab.extend_from_slice(&compression.to_le_bytes());
+
+        let mut offset = 0u32;
+        for (name, size) in files {
+            cab.extend_from_slice(&size.to_le_bytes());
+            cab.extend_from_slice(&offset.to_le_bytes());
+            cab.extend_from_slice(&folder_index.to_le_bytes());
+            cab.extend_from_slice(&[0; 6]);
+            cab.extend_from_slice(name.as_bytes());
+            cab.push(0);
+            offset += size;
+        }
+
+        for (data, uncompressed) in blocks {
+            let checksum = checksum(data, 0) ^ (data.len() as u32 | (*uncompressed as u32) << 16);
+            cab.extend_from_slice(&checksum.to_le_bytes());
+            cab.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            cab.extend_from_slice(&(*uncompressed as u16).to_le_bytes());
+            cab.extend_from_slice(data);
+        }
+
+        cab
+    }
+
+    fn read_folder(cab: &[u8]) -> Vec<u8> {
+        let cabinet = Cabinet::read(&mut io::Cursor::new(cab)).unwrap();
+        let mut stream = Vec::new();
+        cabinet
+            .folder_reader(io::Cursor::new(cab), 0)
+            .unwrap()
+            .read_to_end(&mut stream)
+            .unwrap();
+        stream
+    }
+
+    /// Data that doesn't compress too well, similar to the contents of a lib
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lzx() {
+        // Packs bits into 16-bit little endian words, most significant bit first
+        struct Bits {
+            out: Vec<u8>,
+            word: u16,
+            len: u8,
+        }
+
+        impl Bits {
+            fn push(&mut self, value: u32, bits: u8) {
+                for i in (0..bits).rev() {
+                    self.word = (self.word << 1) | ((value >> i) & 1) as u16;
+                    self.len += 1;
+                    if self.len == 16 {
+                        self.out.extend_from_slice(&self.word.to_le_bytes());
+                        self.word = 0;
+                        self.len = 0;
+                    }
+                }
+            }
+
+            fn align(&mut self) {
+                if self.len != 0 {
+                    self.push(0, 16 - self.len);
+                }
+            }
+        }
+
+        // Encodes each 32k chunk as an LZX uncompressed block
+        let data = noise(32 * 1024 * 2 + 1001, 7);
+        let blocks: Vec<_> = data
+            .chunks(32 * 1024)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut bits = Bits {
+                    out: Vec::new(),
+                    word: 0,
+                    len: 0,
+                };
+
+                // The first chunk starts with the E8 translation flag
+                if i == 0 {
+                    bits.push(0, 1);
+                }
+                bits.push(3, 3);
+                bits.push(chunk.len() as u32 >> 8, 16);
+                bits.push(chunk.len() as u32 & 0xff, 8);
+                bits.align();
+
+                // R0, R1, R2
+                for _ in 0..3 {
+                    bits.out.extend_from_slice(&1u32.to_le_bytes());
+                }
+                bits.out.extend_from_slice(chunk);
+                if chunk.len() % 2 != 0 {
+                    bits.out.push(0);
+                }
+
+                (bits.out, chunk.len())
+            })
+            .collect();
+        assert_eq!(blocks.len(), 3);
+
+        // LZX with a 32k window
+        let cab = build_cab(
+            3 | (15 << 8),
+            &blocks,
+            &[("a.lib", 40_000), ("b.lib", data.len() as u32 - 40_000)],
+            0,
+        );
+
+        assert_eq!(read_folder(&cab), data);
+
+        // Ensure the fixture is also valid according to the `cab` crate
+        let mut expected = Vec::new();
+        cab::Cabinet::new(io::Cursor::new(&cab))
+            .unwrap()
+            .read_file("b.lib")
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(expected, &data[40_000..]);
+    }
+
+    #[test]
+    fn mszip_blocks() {
+        // The first block is a regular deflate stream
+        let first = noise(32 * 1024, 3);
+        let mut compressed = Vec::with_capacity(first.len() * 2);
+        compressed.extend_from_slice(b"CK");
+        flate2::Compress::new(flate2::Compression::default(), false)
+            .compress_vec(&first, &mut compressed, flate2::FlushCompress::Finish)
+            .unwrap();
+
+        // The second block is only a match that copies 258 bytes from 1000
+        // bytes back, ie. from the previous block, which requires the
+        // previous block to be used as the dictionary
+        let mut second = b"CK".to_vec();
+        {
+            let mut bits = 0u64;
+            let mut len = 0;
+            let mut push = |value: u64, count: u32| {
+                bits |= value << len;
+                len += count;
+            };
+            // Huffman codes are packed starting with the most significant bit
+            let code = |code: u64, count: u32| {
+                (0..count).fold(0, |acc, i| acc | ((code >> i) & 1) << (count - 1 - i))
+            };
+
+            push(1, 1); // BFINAL
+            push(1, 2); // Fixed Huffman codes
+            push(code(0b1100_0101, 8), 8); // length 258
+            push(code(19, 5), 5); // distance 769-1024
+            push(1000 - 769, 8);
+            push(code(0, 7), 7); // end of block
+
+            second.extend_from_slice(&bits.to_le_bytes()[..len.div_ceil(8) as usize]);
+        }
+
+        let cab = build_cab(
+            1,
+            &[(compressed, first.len()), (second, 258)],
+            &[("a.h", first.len() as u32 + 258)],
+            0,
+        );
+
+        let stream = read_folder(&cab);
+        assert_eq!(&stream[..first.len()], &first[..]);
+        assert_eq!(&stream[first.len()..], &first[first.len() - 1000..][..258]);
+    }
+
+    #[test]
+    fn rejects_split_cabinets() {
+        let cab = build_cab(0, &[(b"split".to_vec(), 5)], &[("a.h", 5)], 0xfffd);
+        let err = Cabinet::read(&mut io::Cursor::new(&cab)).err().unwrap();
+        assert!(err.to_string().contains("split across multiple cabinets"));
+
+        // The last block of a folder that continues in the next cabinet has
+        // an uncompressed size of 0
+        let cab = build_cab(0, &[(b"split".to_vec(), 0)], &[("a.h", 5)], 0);
+        let cabinet = Cabinet::read(&mut io::Cursor::new(&cab)).unwrap();
+        let err = cabinet
+            .folder_reader(io::Cursor::new(&cab), 0)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("split across multiple cabinets"));
+    }
+
+    /// Checks every CAB in `XWIN_TEST_CABS`, eg. `.xwin-cache/dl/sha256` after
+    /// a download, against the `cab` crate, as real payloads can't be checked in
+    #[test]
+    #[ignore]
+    fn real_cabs() {
+        let dir = std::env::var("XWIN_TEST_CABS").expect("XWIN_TEST_CABS is not set");
+        let mut checked = 0;
+
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let contents = std::fs::read(path).unwrap();
+            if !contents.starts_with(SIGNATURE) {
+                continue;
+            }
+
+            let cabinet = Cabinet::read(&mut io::Cursor::new(&contents)).unwrap();
+            let mut reference = cab::Cabinet::new(io::Cursor::new(&contents)).unwrap();
+
+            for (i, folder) in cabinet.folders.iter().enumerate() {
+                let mut stream = Vec::new();
+                cabinet
+                    .folder_reader(io::Cursor::new(&contents), i)
+                    .unwrap()
+                    .read_to_end(&mut stream)
+                    .unwrap();
+
+                for file in &folder.files {
+                    let start = file.offset as usize;
+                    let actual = &stream[start..start + file.size as usize];
+
+                    let mut expected = Vec::new();
+                    reference
+                        .read_file(&file.name)
+                        .unwrap()
+                        .read_to_end(&mut expected)
+                        .unwrap();
+                    assert!(actual == expected, "{} differs", file.name);
+                }
+            }
+
+            checked += 1;
+        }
+
+        assert!(checked > 0, "no CABs were found");
+    }
+}
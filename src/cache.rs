@@ -0,0 +1,815 @@
+//! Every downloaded file with a known checksum is stored in the download cache
+//! by its sha256, so that identical files, eg. the same CAB referenced by
+//! multiple versions of an SDK, are only retrieved and stored once. An index
+//! of the filenames each blob has been retrieved as is kept alongside them.
+
+use crate::{manifest, util::Sha256, Ctx, Error, Path, PathBuf, Payload};
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The directory under the download cache that blobs are stored in
+const BLOB_DIR: &str = "sha256";
+/// The name of the index file in the download cache
+const INDEX_FILE: &str = "index.json";
+/// The file locked while the index is being updated
+const LOCK_FILE: &str = "index.json.lock";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Blob {
+    pub(crate) size: u64,
+    /// Every filename the blob has been retrieved as
+    pub(crate) filenames: BTreeSet<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Index {
+    /// The blobs in the cache, keyed by their hex encoded sha256
+    pub(crate) blobs: BTreeMap<String, Blob>,
+}
+
+impl Index {
+    /// Reads the index from the download cache, a missing or invalid index
+    /// is treated as empty, as it is recreated as blobs are retrieved
+    pub(crate) fn read(dl_dir: &Path) -> Self {
+        let path = dl_dir.join(INDEX_FILE);
+
+        match std::fs::read(&path) {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(index) => index,
+                Err(err) => {
+                    tracing::warn!(error = %err, "failed to deserialize cache index '{}'", path);
+                    Self::default()
+                }
+            },
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!(error = %err, "failed to read cache index '{}'", path);
+                }
+                Self::default()
+            }
+        }
+    }
+
+    /// Applies `update` to the index on disk and writes it if it was changed,
+    /// returning the updated index. The index is re-read while holding an
+    /// exclusive lock, so that multiple processes sharing the same cache
+    /// don't lose each other's changes.
+    pub(crate) fn update(
+        dl_dir: &Path,
+        update: impl FnOnce(&mut Self) -> bool,
+    ) -> Result<Self, Error> {
+        use fs2::FileExt;
+        use std::io::Write;
+
+        std::fs::create_dir_all(dl_dir).with_context(|| format!("unable to create {}", dl_dir))?;
+
+        let lock_path = dl_dir.join(LOCK_FILE);
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("failed to open cache index lock '{}'", lock_path))?;
+        lock.lock_exclusive()
+            .with_context(|| format!("failed to lock cache index lock '{}'", lock_path))?;
+
+        let mut index = Self::read(dl_dir);
+        if !update(&mut index) {
+            return Ok(index);
+        }
+
+        let path = dl_dir.join(INDEX_FILE);
+        let mut tmp = tempfile::NamedTempFile::new_in(dl_dir)
+            .with_context(|| format!("failed to create temp file in {}", dl_dir))?;
+        let contents = serde_json::to_vec_pretty(&index)?;
+        tmp.write_all(&contents)
+            .with_context(|| format!("failed to write cache index '{}'", tmp.path().display()))?;
+        tmp.persist(&path)
+            .with_context(|| format!("failed to persist cache index '{}'", path))?;
+
+        Ok(index)
+    }
+
+    /// Records that the blob has been retrieved as the specified filename,
+    /// returning true if the index was changed
+    pub(crate) fn insert(&mut self, sha256: &Sha256, size: u64, filename: &Path) -> bool {
+        let blob = self.blobs.entry(sha256.to_string()).or_default();
+
+        let changed = blob.size != size;
+        blob.size = size;
+        blob.filenames.insert(filename.to_owned()) || changed
+    }
+}
+
+/// Gets the path of the blob with the specified checksum in the download cache
+#[inline]
+pub(crate) fn blob_path(dl_dir: &Path, sha256: &Sha256) -> PathBuf {
+    let mut path = dl_dir.join(BLOB_DIR);
+    path.push(sha256.to_string());
+    path
+}
+
+/// Takes an exclusive lock on the blob with the specified checksum, so that
+/// it is only retrieved by one of the processes sharing the cache at a time.
+/// The lock is released when the returned file is dropped.
+pub(crate) fn lock_blob(dl_dir: &Path, sha256: &Sha256) -> Result<std::fs::File, Error> {
+    use fs2::FileExt;
+
+    let blob_dir = dl_dir.join(BLOB_DIR);
+    std::fs::create_dir_all(&blob_dir).with_context(|| format!("unable to create {}", blob_dir))?;
+
+    let lock_path = blob_dir.join(format!("{}.lock", sha256));
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("failed to open blob lock '{}'", lock_path))?;
+    lock.lock_exclusive()
+        .with_context(|| format!("failed to lock blob lock '{}'", lock_path))?;
+
+    Ok(lock)
+}
+
+/// The cached state of a single blob, or unpacked payload, in the cache
+pub struct PayloadStats {
+    pub sha256: String,
+    /// The filenames the blob was retrieved or unpacked as
+    pub filenames: BTreeSet<PathBuf>,
+    /// The size of the blob in the download cache, `None` if it is not present
+    pub downloaded: Option<u64>,
+    /// The size of the unpacked contents, `None` if it is not unpacked
+    pub unpacked: Option<u64>,
+}
+
+/// The portion of the cache referenced by a package manifest
+pub struct ManifestStats {
+    pub filename: PathBuf,
+    pub size: u64,
+    /// The number of payloads, including CABs, in the cache that are
+    /// referenced by the package manifest
+    pub payloads: u32,
+    /// The total download size of the referenced payloads
+    pub downloaded: u64,
+}
+
+pub struct Stats {
+    pub payloads: Vec<PayloadStats>,
+    pub manifests: Vec<ManifestStats>,
+}
+
+/// A single item removed from the cache
+pub struct Removed {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// An invalid item found in the cache
+pub struct Invalid {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Reads the metadata written when a payload has been successfully unpacked,
+/// `None` if it is missing or invalid
+fn read_unpack_meta(unpack_dir: &Path) -> Option<crate::unpack::UnpackMeta> {
+    let contents = std::fs::read(unpack_dir.join(".unpack")).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Gets the entries in a directory, ignoring any that are not utf-8, a missing
+/// directory is treated as empty
+fn read_dir(dir: &Path) -> Result<Vec<(PathBuf, std::fs::Metadata)>, Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("unable to read {}", dir)),
+    };
+
+    let mut items = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("unable to read entry from {}", dir))?;
+
+        let path = match PathBuf::from_path_buf(entry.path()) {
+            Ok(path) => path,
+            Err(pb) => {
+                tracing::warn!("ignoring non utf-8 path {}", pb.display());
+                continue;
+            }
+        };
+
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("unable to get metadata for {}", path))?;
+        items.push((path, metadata));
+    }
+
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(items)
+}
+
+/// Gets the number of files, and their total size, in a directory
+fn dir_size(dir: &Path) -> Result<(u32, u64), Error> {
+    read_dir(dir)?
+        .into_iter()
+        .try_fold((0, 0), |(num_files, size), (path, md)| {
+            if md.is_dir() {
+                let (sub_files, sub_size) = dir_size(&path)?;
+                Ok((num_files + sub_files, size + sub_size))
+            } else {
+                Ok((num_files + 1, size + md.len()))
+            }
+        })
+}
+
+/// Removes the specified blobs from the index
+fn remove_from_index(ctx: &Ctx, dl_dir: &Path, blobs: &[String]) -> Result<(), Error> {
+    let mut index = ctx.index.lock();
+    *index = Index::update(dl_dir, |index| {
+        let mut changed = false;
+        for name in blobs {
+            changed |= index.blobs.remove(name).is_some();
+        }
+        changed
+    })?;
+    Ok(())
+}
+
+/// Gathers the size of every blob and unpacked payload in the cache, as well
+/// as how much of the cache is referenced by each cached package manifest
+pub fn stats(ctx: &Ctx) -> Result<Stats, Error> {
+    let dl_dir = ctx.work_dir.join("dl");
+    let mut payloads = BTreeMap::new();
+
+    {
+        let index = ctx.index.lock();
+
+        for (path, md) in read_dir(&dl_dir.join(BLOB_DIR))? {
+            // Skip partial downloads, locks, and anything else that isn't a blob
+            let sha256 = match path.file_name() {
+                Some(name) if name.parse::<Sha256>().is_ok() => name.to_owned(),
+                _ => continue,
+            };
+
+            let filenames = index
+                .blobs
+                .get(&sha256)
+                .map(|blob| blob.filenames.clone())
+                .unwrap_or_default();
+
+            payloads.insert(
+                sha256.clone(),
+                PayloadStats {
+                    sha256,
+                    filenames,
+                    downloaded: Some(md.len()),
+                    unpacked: None,
+                },
+            );
+        }
+    }
+
+    for (path, md) in read_dir(&ctx.work_dir.join("unpack"))? {
+        if !md.is_dir() {
+            continue;
+        }
+
+        if let Some(um) = read_unpack_meta(&path) {
+            let sha256 = um.sha256.to_string();
+            let ps = payloads
+                .entry(sha256.clone())
+                .or_insert_with(|| PayloadStats {
+                    sha256,
+                    filenames: BTreeSet::new(),
+                    downloaded: None,
+                    unpacked: None,
+                });
+
+            if let Some(name) = path.file_name() {
+                ps.filenames.insert(name.into());
+            }
+            ps.unpacked = Some(ps.unpacked.unwrap_or_default() + um.decompressed);
+        }
+    }
+
+    // Package manifests are stored by name, unless they were retrieved with
+    // a checksum, in which case they are stored as a blob
+    let mut pkg_manifests = Vec::new();
+
+    for (path, md) in read_dir(&dl_dir)? {
+        match path.file_name() {
+            Some(name) if name.starts_with("pkg_manifest_") && md.is_file() => {
+                pkg_manifests.push((PathBuf::from(name), path.clone(), md.len()));
+            }
+            _ => {}
+        }
+    }
+
+    for ps in payloads.values() {
+        let (filename, size) = match (
+            ps.filenames
+                .iter()
+                .find(|name| name.as_str().starts_with("pkg_manifest_")),
+            ps.downloaded,
+        ) {
+            (Some(filename), Some(size)) => (filename, size),
+            _ => continue,
+        };
+
+        let sha256: Sha256 = match ps.sha256.parse() {
+            Ok(sha256) => sha256,
+            Err(_) => continue,
+        };
+
+        pkg_manifests.push((filename.clone(), blob_path(&dl_dir, &sha256), size));
+    }
+
+    let mut manifests = Vec::new();
+
+    for (filename, path, size) in pkg_manifests {
+        let pkg_manifest = match std::fs::read(&path)
+            .map_err(Error::from)
+            .and_then(|contents| manifest::PackageManifest::from_slice(&contents))
+        {
+            Ok(pm) => pm,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to read package manifest {}", path);
+                continue;
+            }
+        };
+
+        let mut referenced = BTreeSet::new();
+        for payload in pkg_manifest
+            .packages
+            .values()
+            .flat_map(|mi| mi.payloads.iter())
+        {
+            referenced.insert(payload.sha256.to_string());
+        }
+
+        let (num, downloaded) = referenced
+            .iter()
+            .filter_map(|sha256| payloads.get(sha256)?.downloaded)
+            .fold((0, 0), |(num, size), dl| (num + 1, size + dl));
+
+        manifests.push(ManifestStats {
+            filename,
+            size,
+            payloads: num,
+            downloaded,
+        });
+    }
+
+    Ok(Stats {
+        payloads: payloads.into_values().collect(),
+        manifests,
+    })
+}
+
+/// Gets every file in the download cache that is stored by name rather than
+/// by checksum, ie. manifests retrieved without a checksum, and files
+/// downloaded before blobs were stored by their checksum
+fn named_files(
+    dl_dir: &Path,
+    dir: &Path,
+    files: &mut Vec<(PathBuf, std::fs::Metadata)>,
+) -> Result<(), Error> {
+    for (path, md) in read_dir(dir)? {
+        if dir == dl_dir && matches!(path.file_name(), Some(BLOB_DIR | INDEX_FILE | LOCK_FILE)) {
+            continue;
+        }
+
+        if md.is_dir() {
+            named_files(dl_dir, &path, files)?;
+        } else {
+            files.push((path, md));
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the names the package manifest has been cached as, `None` if the
+/// checksum of the package manifest is not known
+fn current_pkg_manifests(
+    ctx: &Ctx,
+    dl_dir: &Path,
+    pkg_manifest: &manifest::PackageManifest,
+) -> Result<Option<BTreeSet<PathBuf>>, Error> {
+    let sha256 = match &pkg_manifest.sha256 {
+        Some(sha256) => sha256,
+        None => return Ok(None),
+    };
+
+    // Package manifests retrieved with a checksum are stored as blobs
+    let mut current = ctx
+        .index
+        .lock()
+        .blobs
+        .get(&sha256.to_string())
+        .map(|blob| blob.filenames.clone())
+        .unwrap_or_default();
+
+    for (path, md) in read_dir(dl_dir)? {
+        let filename = match path.file_name() {
+            Some(name) if name.starts_with("pkg_manifest_") && md.is_file() => name,
+            _ => continue,
+        };
+
+        if let Ok(chksum) = std::fs::File::open(&path).and_then(Sha256::digest_reader) {
+            if chksum == *sha256 {
+                current.insert(filename.into());
+            }
+        }
+    }
+
+    Ok(Some(current))
+}
+
+/// Gets the name of the package manifest referenced by a cached channel
+/// manifest, `None` if it can't be read
+fn channel_pkg_manifest(path: &Path) -> Option<PathBuf> {
+    let contents = std::fs::read(path).ok()?;
+    let manifest: manifest::Manifest = serde_json::from_slice(&contents).ok()?;
+    let (_, filename) = manifest.package_manifest().ok()?;
+    Some(filename.into())
+}
+
+/// Removes every blob, and unpacked payload, from the cache that is not
+/// referenced by the specified payloads, as well as any partial downloads,
+/// manifests other than the current one, and files downloaded before blobs
+/// were stored by their checksum that aren't referenced
+pub fn prune(
+    ctx: &Ctx,
+    pkg_manifest: &manifest::PackageManifest,
+    payloads: &[Payload],
+    dry_run: bool,
+) -> Result<Vec<Removed>, Error> {
+    let mut referenced = BTreeSet::new();
+    // The paths payloads were stored at before blobs were stored by checksum
+    let mut referenced_paths = BTreeSet::new();
+
+    // If the package manifest was retrieved with a checksum it is also a blob
+    if let Some(sha256) = &pkg_manifest.sha256 {
+        referenced.insert(sha256.to_string());
+    }
+
+    for payload in payloads {
+        referenced.insert(payload.sha256.to_string());
+        referenced_paths.insert(payload.filename.clone());
+
+        if payload.filename.extension() == Some("msi") {
+            for cab in crate::download::find_cabs(&pkg_manifest.packages, payload)? {
+                referenced.insert(cab.sha256.to_string());
+                referenced_paths.insert(PathBuf::from(format!(
+                    "{}/{}",
+                    payload.filename.file_stem().unwrap_or_default(),
+                    cab.filename
+                )));
+            }
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut removed_blobs = Vec::new();
+    let dl_dir = ctx.work_dir.join("dl");
+
+    for (path, md) in read_dir(&dl_dir.join(BLOB_DIR))? {
+        let name = path.file_name().unwrap_or_default();
+        // Lock files are kept as long as their blob is
+        if referenced.contains(name.strip_suffix(".lock").unwrap_or(name)) {
+            continue;
+        }
+
+        if !dry_run {
+            std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path))?;
+        }
+
+        removed_blobs.push(name.to_owned());
+        removed.push(Removed {
+            path,
+            size: md.len(),
+        });
+    }
+
+    let current_manifests = current_pkg_manifests(ctx, &dl_dir, pkg_manifest)?;
+
+    let mut named = Vec::new();
+    named_files(&dl_dir, &dl_dir, &mut named)?;
+
+    for (path, md) in named {
+        let rel_path = path.strip_prefix(&dl_dir).unwrap_or(&path);
+        let name = rel_path.as_str();
+
+        // Partial downloads outside of the blob directory have no checksum
+        // to resume against, and manifests are only kept if they are for the
+        // currently selected package manifest. If the package manifest's
+        // checksum isn't known, eg. from an older lockfile, all are kept.
+        let is_referenced = if name.ends_with(".partial") {
+            false
+        } else if name.starts_with("pkg_manifest_") {
+            match &current_manifests {
+                Some(current) => current.contains(rel_path),
+                None => true,
+            }
+        } else if name.starts_with("manifest_") && name.ends_with(".json") {
+            match (&current_manifests, channel_pkg_manifest(&path)) {
+                (Some(current), Some(pm)) => current.contains(&pm),
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+        } else {
+            referenced_paths.contains(rel_path)
+        };
+
+        if is_referenced {
+            continue;
+        }
+
+        if !dry_run {
+            std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path))?;
+        }
+
+        removed.push(Removed {
+            path,
+            size: md.len(),
+        });
+    }
+
+    for (path, md) in read_dir(&ctx.work_dir.join("unpack"))? {
+        if !md.is_dir() {
+            continue;
+        }
+
+        let is_referenced = read_unpack_meta(&path).into_iter().any(|um| {
+            payloads.iter().any(|payload| {
+                payload.sha256 == um.sha256 && Some(payload.filename.as_str()) == path.file_name()
+            })
+        });
+
+        if is_referenced {
+            continue;
+        }
+
+        let (_, size) = dir_size(&path)?;
+        if !dry_run {
+            std::fs::remove_dir_all(&path).with_context(|| format!("failed to remove {}", path))?;
+        }
+
+        removed.push(Removed { path, size });
+    }
+
+    if !dry_run && !removed_blobs.is_empty() {
+        remove_from_index(ctx, &dl_dir, &removed_blobs)?;
+    }
+
+    Ok(removed)
+}
+
+/// Rehashes every blob in the cache, and checks that every unpacked payload
+/// matches its unpack metadata. If `fix` is true, invalid items are removed
+/// from the cache so that they are retrieved or unpacked again when needed.
+pub fn verify(ctx: &Ctx, fix: bool) -> Result<Vec<Invalid>, Error> {
+    use rayon::prelude::*;
+
+    let dl_dir = ctx.work_dir.join("dl");
+
+    let blobs = read_dir(&dl_dir.join(BLOB_DIR))?;
+    let mut invalid: Vec<_> = ctx.thread_pool()?.install(|| {
+        blobs
+            .into_par_iter()
+            .filter_map(|(path, _md)| {
+                let name = path.file_name().unwrap_or_default();
+                if name.ends_with(".partial") || name.ends_with(".lock") {
+                    return None;
+                }
+
+                let expected: Sha256 = match name.parse() {
+                    Ok(expected) => expected,
+                    Err(_) => {
+                        return Some(Invalid {
+                            path,
+                            reason: "filename is not a sha256 checksum".to_owned(),
+                        });
+                    }
+                };
+
+                let reason = match std::fs::File::open(&path).and_then(Sha256::digest_reader) {
+                    Ok(chksum) if chksum == expected => return None,
+                    Ok(chksum) => format!("checksum mismatch, actual {}", chksum),
+                    Err(err) => format!("failed to read: {}", err),
+                };
+
+                Some(Invalid { path, reason })
+            })
+            .collect()
+    });
+
+    for (path, md) in read_dir(&ctx.work_dir.join("unpack"))? {
+        if !md.is_dir() {
+            continue;
+        }
+
+        // The unpack metadata is removed when the payload is splatted without
+        // `--copy`, as the unpacked files are moved, so there's nothing to check
+        if !path.join(".unpack").exists() {
+            tracing::warn!("skipping {}, it has no unpack metadata", path);
+            continue;
+        }
+
+        #[allow(clippy::single_match_else)]
+        let um = match read_unpack_meta(&path) {
+            Some(um) => um,
+            None => {
+                invalid.push(Invalid {
+                    path,
+                    reason: "unpack metadata is invalid".to_owned(),
+                });
+                continue;
+            }
+        };
+
+        let (mut num_files, mut size) = dir_size(&path)?;
+
+        // These are written alongside the unpacked contents
+        for meta_file in [".unpack", crate::unpack::INDEX_FILE, "tree.txt"].iter() {
+            if let Ok(md) = std::fs::metadata(path.join(meta_file)) {
+                num_files -= 1;
+                size -= md.len();
+            }
+        }
+
+        if num_files != um.num_files || size != um.decompressed {
+            invalid.push(Invalid {
+                path,
+                reason: format!(
+                    "expected {} files ({} bytes) but found {} files ({} bytes)",
+                    um.num_files, um.decompressed, num_files, size
+                ),
+            });
+        }
+    }
+
+    if fix && !invalid.is_empty() {
+        let mut removed_blobs = Vec::new();
+
+        for inv in &invalid {
+            if inv.path.is_dir() {
+                std::fs::remove_dir_all(&inv.path)
+            } else {
+                if let Some(name) = inv.path.file_name() {
+                    removed_blobs.push(name.to_owned());
+                }
+                std::fs::remove_file(&inv.path)
+            }
+            .with_context(|| format!("failed to remove {}", inv.path))?;
+        }
+
+        if !removed_blobs.is_empty() {
+            remove_from_index(ctx, &dl_dir, &removed_blobs)?;
+        }
+    }
+
+    Ok(invalid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn index() {
+        let td = tempfile::TempDir::new().unwrap();
+        let dl_dir = Path::from_path(td.path()).unwrap();
+
+        let mut index = Index::read(dl_dir);
+        assert!(index.blobs.is_empty());
+
+        let sha = Sha256([1; 32]);
+        assert!(index.insert(&sha, 10, Path::new("one/a.cab")));
+        assert!(index.insert(&sha, 10, Path::new("two/a.cab")));
+        assert!(!index.insert(&sha, 10, Path::new("one/a.cab")));
+
+        // Another process adding a blob after this one read the index
+        let other = Sha256([2; 32]);
+        Index::update(dl_dir, |index| index.insert(&other, 20, Path::new("b.cab"))).unwrap();
+
+        let index = Index::update(dl_dir, |disk| {
+            index.blobs.iter().fold(false, |changed, (sha256, blob)| {
+                let sha256: Sha256 = sha256.parse().unwrap();
+                blob.filenames.iter().fold(changed, |changed, filename| {
+                    disk.insert(&sha256, blob.size, filename) || changed
+                })
+            })
+        })
+        .unwrap();
+        assert_eq!(index.blobs.len(), 2);
+
+        let index = Index::read(dl_dir);
+        let blob = &index.blobs[&sha.to_string()];
+        assert_eq!(blob.size, 10);
+        assert_eq!(blob.filenames.len(), 2);
+        assert_eq!(index.blobs[&other.to_string()].size, 20);
+
+        assert_eq!(
+            blob_path(dl_dir, &sha),
+            dl_dir.join(format!("sha256/{}", sha))
+        );
+    }
+
+    #[test]
+    fn stats_skips_stray_files() {
+        let ctx = Ctx::with_temp(crate::util::ProgressTarget::Hidden).unwrap();
+        let dl_dir = ctx.work_dir.join("dl");
+
+        let sha256 = Sha256::digest(b"blob");
+        let blob = blob_path(&dl_dir, &sha256);
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(&blob, b"blob").unwrap();
+        std::fs::write(dl_dir.join("sha256/junk"), b"junk").unwrap();
+        std::fs::write(dl_dir.join(format!("sha256/{}.lock", sha256)), b"").unwrap();
+
+        let stats = stats(&ctx).unwrap();
+        assert_eq!(stats.payloads.len(), 1);
+        assert_eq!(stats.payloads[0].sha256, sha256.to_string());
+    }
+
+    #[test]
+    fn verify_skips_moved_unpacks() {
+        let ctx = Ctx::with_temp(crate::util::ProgressTarget::Hidden).unwrap();
+        let unpack_dir = ctx.work_dir.join("unpack");
+
+        // A payload splatted without `--copy` has its metadata removed
+        let moved = unpack_dir.join("moved.vsix");
+        std::fs::create_dir_all(&moved).unwrap();
+        std::fs::write(moved.join("tree.txt"), b"").unwrap();
+
+        let invalid_meta = unpack_dir.join("invalid.vsix");
+        std::fs::create_dir_all(&invalid_meta).unwrap();
+        std::fs::write(invalid_meta.join(".unpack"), b"{").unwrap();
+
+        let invalid = verify(&ctx, false).unwrap();
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].path, invalid_meta);
+    }
+
+    #[test]
+    fn prunes_named_files() {
+        let ctx = Ctx::with_temp(crate::util::ProgressTarget::Hidden).unwrap();
+        let dl_dir = ctx.work_dir.join("dl");
+
+        let channel_manifest = |sha256: &str| {
+            format!(
+                r#"{{"channelItems":[{{"id":"pkgs","version":"1","type":"Manifest","payloads":[{{"fileName":"VisualStudio.vsman","sha256":"{}","size":1,"url":"https://example.com"}}]}}]}}"#,
+                sha256
+            )
+        };
+
+        let current = "a".repeat(64);
+        let stale = "b".repeat(64);
+        let files = [
+            ("manifest_17.json", channel_manifest(&current)),
+            ("manifest_16.json", channel_manifest(&stale)),
+            (
+                &format!("pkg_manifest_{}.vsman", current),
+                r#"{"packages":[]}"#.to_owned(),
+            ),
+            (
+                &format!("pkg_manifest_{}.vsman", stale),
+                r#"{"packages": []}"#.to_owned(),
+            ),
+            ("pkg_manifest_c.vsman.partial", String::new()),
+            ("old/a.msi", String::new()),
+        ];
+
+        for (name, contents) in &files {
+            let path = dl_dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let pkg_manifest = manifest::PackageManifest::from_slice(br#"{"packages":[]}"#).unwrap();
+        let removed: BTreeSet<_> = prune(&ctx, &pkg_manifest, &[], false)
+            .unwrap()
+            .into_iter()
+            .map(|rem| rem.path.strip_prefix(&dl_dir).unwrap().to_owned())
+            .collect();
+
+        let expected: BTreeSet<PathBuf> = [
+            "manifest_16.json".to_owned(),
+            format!("pkg_manifest_{}.vsman", stale),
+            "pkg_manifest_c.vsman.partial".to_owned(),
+            "old/a.msi".to_owned(),
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(removed, expected);
+
+        assert!(dl_dir.join("manifest_17.json").exists());
+        assert!(dl_dir
+            .join(format!("pkg_manifest_{}.vsman", current))
+            .exists());
+    }
+}
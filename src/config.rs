@@ -0,0 +1,107 @@
+//! The optional config file, for settings that are tedious or unwieldy to
+//! pass on every invocation, eg. the mirrors that are required in a
+//! particular network.
+//!
+//! ```toml
+//! [[mirror]]
+//! from = "https://download.visualstudio.microsoft.com/"
+//! to = "https://mirror.example.com/vs/"
+//!
+//! [[mirror]]
+//! from = "https://aka.ms/vs/"
+//! to = "https://mirror.example.com/channels/"
+//! ```
+
+use crate::{Error, Mirror, Path};
+use anyhow::Context as _;
+use serde::Deserialize;
+
+/// The settings read from a config file
+#[derive(Default, Debug)]
+pub struct Config {
+    /// The url rewrite rules, in the order they are specified
+    pub mirrors: Vec<Mirror>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    mirror: Vec<MirrorEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MirrorEntry {
+    from: String,
+    to: String,
+}
+
+impl Config {
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file '{}'", path))?;
+        contents
+            .parse()
+            .with_context(|| format!("invalid config file '{}'", path))
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let file: ConfigFile = toml::from_str(s)?;
+
+        Ok(Self {
+            mirrors: file
+                .mirror
+                .into_iter()
+                .map(|mirror| Mirror::new(mirror.from, mirror.to))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mirrors() {
+        let config: Config = r#"
+[[mirror]]
+from = "https://download.visualstudio.microsoft.com/"
+to = "https://mirror.example.com/vs/?a=b"
+
+[[mirror]]
+from = "https://aka.ms/vs/"
+to = "file:///mnt/mirror/channels/"
+"#
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            config
+                .mirrors
+                .iter()
+                .map(|mirror| (mirror.from.as_str(), mirror.to.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "https://download.visualstudio.microsoft.com/",
+                    "https://mirror.example.com/vs/?a=b"
+                ),
+                ("https://aka.ms/vs/", "file:///mnt/mirror/channels/"),
+            ]
+        );
+
+        assert!("".parse::<Config>().unwrap().mirrors.is_empty());
+        assert!("[[mirror]]\nfrom = \"\"\nto = \"https://example.com\""
+            .parse::<Config>()
+            .is_err());
+        assert!("[[mirrors]]\nfrom = \"a\"\nto = \"b\""
+            .parse::<Config>()
+            .is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     util::{ProgressTarget, Sha256},
-    Path, PathBuf, WorkItem,
+    FetchError, Path, PathBuf, WorkItem,
 };
 use anyhow::{Context as _, Error};
 
@@ -17,54 +17,180 @@ pub enum Unpack {
 pub struct Ctx {
     pub work_dir: PathBuf,
     pub tempdir: Option<tempfile::TempDir>,
-    pub client: ureq::Agent,
+    /// Retrieves the contents of every url, defaults to a [`crate::HttpClient`]
+    /// without a proxy, see [`crate::HttpConfig::from_env`]
+    pub fetcher: Box<dyn crate::Fetcher>,
     pub draw_target: ProgressTarget,
+    /// If set, network access is forbidden and any item that is not already
+    /// present and valid in the download cache results in an error
+    pub offline: bool,
+    /// The number of times a failed download is retried before giving up.
+    /// Partially downloaded files are resumed where possible.
+    pub retries: u32,
+    /// Rewrite rules applied to every url before it is retrieved
+    pub mirrors: Vec<Mirror>,
+    /// The number of threads used to download, unpack, and splat packages,
+    /// defaults to the number of logical CPUs
+    pub jobs: Option<usize>,
+    /// The maximum number of downloads that can be in progress at any one
+    /// time, regardless of the number of `jobs`
+    pub max_concurrent_downloads: Option<usize>,
+    /// The maximum combined download rate, in bytes per second
+    pub limit_rate: Option<u64>,
+    /// Selects which files are unpacked from each payload, defaults to all of
+    /// the files
+    pub unpack_filter: crate::UnpackFilter,
+    /// If set, the Authenticode signatures of every MSI and CAB must chain to
+    /// one of these roots, they are verified before the payload is unpacked
+    pub signature_roots: Option<crate::SignatureRoots>,
+    /// Limits the number of concurrent downloads
+    downloads: Semaphore,
+    /// Limits the combined rate of all downloads
+    rate_limiter: RateLimiter,
+    /// The index of the blobs in the download cache
+    pub(crate) index: parking_lot::Mutex<crate::cache::Index>,
+    /// Locks for each blob currently being retrieved
+    blob_locks: parking_lot::Mutex<
+        std::collections::BTreeMap<[u8; 32], std::sync::Arc<parking_lot::Mutex<()>>>,
+    >,
 }
 
-impl Ctx {
-    fn http_client() -> Result<ureq::Agent, Error> {
-        #[cfg(feature = "native-tls")]
-        {
-            use std::sync::Arc;
-            let mut builder =
-                ureq::builder().tls_connector(Arc::new(native_tls_crate::TlsConnector::new()?));
-            if let Ok(proxy) = std::env::var("https_proxy") {
-                let proxy = ureq::Proxy::new(proxy)?;
-                builder = builder.proxy(proxy);
-            };
-            Ok(builder.build())
+/// A rewrite rule for urls, any url that starts with `from` has that prefix
+/// replaced with `to`, eg. to retrieve everything from an internal mirror
+/// instead of the Microsoft servers. Checksums are still enforced for any
+/// rewritten url.
+#[derive(Clone, Debug)]
+pub struct Mirror {
+    pub from: String,
+    pub to: String,
+}
+
+impl Mirror {
+    /// Creates a mirror that rewrites urls starting with `from` to start with
+    /// `to` instead, neither of which can be empty
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Result<Self, Error> {
+        let (from, to) = (from.into(), to.into());
+
+        anyhow::ensure!(
+            !from.is_empty() && !to.is_empty(),
+            "mirror '{}={}' has an empty url prefix",
+            from,
+            to
+        );
+
+        Ok(Self { from, to })
+    }
+}
+
+impl std::str::FromStr for Mirror {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .with_context(|| format!("mirror '{}' is not in the form '<from>=<to>'", s))?;
+
+        Self::new(from, to)
+    }
+}
+
+/// A counting semaphore, the limit is specified on acquisition so that it can
+/// be changed after the [`Ctx`] is constructed
+#[derive(Default)]
+struct Semaphore {
+    in_use: parking_lot::Mutex<usize>,
+    released: parking_lot::Condvar,
+}
+
+impl Semaphore {
+    fn acquire(&self, limit: usize) -> SemaphorePermit<'_> {
+        let mut in_use = self.in_use.lock();
+        while *in_use >= limit {
+            self.released.wait(&mut in_use);
         }
+        *in_use += 1;
 
-        #[cfg(not(feature = "native-tls"))]
-        {
-            let mut builder = ureq::builder();
-            if let Ok(proxy) = std::env::var("https_proxy") {
-                let proxy = ureq::Proxy::new(proxy)?;
-                builder = builder.proxy(proxy);
-            };
-            Ok(builder.build())
+        SemaphorePermit { sem: self }
+    }
+}
+
+struct SemaphorePermit<'sem> {
+    sem: &'sem Semaphore,
+}
+
+impl<'sem> Drop for SemaphorePermit<'sem> {
+    fn drop(&mut self) {
+        *self.sem.in_use.lock() -= 1;
+        self.sem.released.notify_one();
+    }
+}
+
+/// A token bucket shared by every download, which allows bursts of up to one
+/// second's worth of bytes
+#[derive(Default)]
+struct RateLimiter {
+    /// The available tokens, which is negative if bytes have been consumed
+    /// in advance, and when they were last refilled
+    state: parking_lot::Mutex<Option<(f64, std::time::Instant)>>,
+}
+
+impl RateLimiter {
+    /// Consumes tokens for the specified number of bytes, blocking the
+    /// current thread until they are available
+    fn consume(&self, rate: u64, bytes: u64) {
+        let rate = rate as f64;
+
+        let wait = {
+            let mut state = self.state.lock();
+            let now = std::time::Instant::now();
+
+            let (tokens, last) = state.get_or_insert((rate, now));
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+            *last = now;
+            *tokens -= bytes as f64;
+
+            (*tokens < 0.0).then(|| std::time::Duration::from_secs_f64(-*tokens / rate))
+        };
+
+        if let Some(wait) = wait {
+            std::thread::sleep(wait);
         }
     }
+}
 
+impl Ctx {
     pub fn with_temp(dt: ProgressTarget) -> Result<Self, Error> {
         let td = tempfile::TempDir::new()?;
-        let client = Self::http_client()?;
+        let fetcher = Box::new(crate::HttpClient::new(crate::HttpConfig::default()));
 
         Ok(Self {
             work_dir: PathBuf::from_path_buf(td.path().to_owned()).map_err(|pb| {
                 anyhow::anyhow!("tempdir {} is not a valid utf-8 path", pb.display())
             })?,
             tempdir: Some(td),
-            client,
+            fetcher,
             draw_target: dt,
+            offline: false,
+            retries: 3,
+            mirrors: Vec::new(),
+            jobs: None,
+            max_concurrent_downloads: None,
+            limit_rate: None,
+            unpack_filter: Default::default(),
+            signature_roots: None,
+            downloads: Semaphore::default(),
+            rate_limiter: RateLimiter::default(),
+            index: Default::default(),
+            blob_locks: Default::default(),
         })
     }
 
     pub fn with_dir(mut work_dir: PathBuf, dt: ProgressTarget) -> Result<Self, Error> {
-        let client = Self::http_client()?;
+        let fetcher = Box::new(crate::HttpClient::new(crate::HttpConfig::default()));
 
         work_dir.push("dl");
         std::fs::create_dir_all(&work_dir)?;
+        let index = crate::cache::Index::read(&work_dir);
         work_dir.pop();
         work_dir.push("unpack");
         std::fs::create_dir_all(&work_dir)?;
@@ -73,11 +199,26 @@ impl Ctx {
         Ok(Self {
             work_dir,
             tempdir: None,
-            client,
+            fetcher,
             draw_target: dt,
+            offline: false,
+            retries: 3,
+            mirrors: Vec::new(),
+            jobs: None,
+            max_concurrent_downloads: None,
+            limit_rate: None,
+            unpack_filter: Default::default(),
+            signature_roots: None,
+            downloads: Semaphore::default(),
+            rate_limiter: RateLimiter::default(),
+            index: parking_lot::Mutex::new(index),
+            blob_locks: Default::default(),
         })
     }
 
+    /// Retrieves the contents of the url, validating them against the checksum
+    /// if one is specified. This reads the entire contents into memory, so
+    /// [`Self::get_and_validate_path`] should be preferred for large files
     pub fn get_and_validate<P>(
         &self,
         url: impl AsRef<str>,
@@ -85,68 +226,291 @@ impl Ctx {
         checksum: Option<Sha256>,
         progress: indicatif::ProgressBar,
     ) -> Result<bytes::Bytes, Error>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        let cache_path = self.get_and_validate_path(url, path, checksum, progress)?;
+
+        let body =
+            std::fs::read(&cache_path).with_context(|| format!("failed to read {}", cache_path))?;
+        Ok(body.into())
+    }
+
+    /// Retrieves the contents of the url to the download cache, validating
+    /// them against the checksum if one is specified, and returns the path of
+    /// the cached file. The contents are streamed to disk and hashed as they
+    /// are received, so they are never entirely resident in memory.
+    pub fn get_and_validate_path<P>(
+        &self,
+        url: impl AsRef<str>,
+        path: &P,
+        checksum: Option<Sha256>,
+        progress: indicatif::ProgressBar,
+    ) -> Result<PathBuf, Error>
     where
         P: AsRef<Path> + std::fmt::Debug,
     {
         let short_path = path.as_ref();
-        let cache_path = {
-            let mut cp = self.work_dir.clone();
-            cp.push("dl");
-            cp.push(short_path);
-            cp
+        let dl_dir = self.work_dir.join("dl");
+
+        #[allow(clippy::single_match_else)]
+        let expected = match checksum {
+            Some(expected) => expected,
+            None => {
+                // Without a checksum there's nothing to address the contents
+                // by, so they are just stored by name
+                let cache_path = dl_dir.join(short_path);
+
+                if cache_path.exists() {
+                    let len = std::fs::metadata(&cache_path).map_or(0, |md| md.len());
+                    progress.inc_length(len);
+                    progress.inc(len);
+                } else {
+                    self.download(url.as_ref(), short_path, &cache_path, None, &progress)?;
+                }
+
+                return Ok(cache_path);
+            }
         };
 
-        if cache_path.exists() {
-            tracing::debug!("verifying existing cached dl file");
-
-            match std::fs::read(&cache_path) {
-                Ok(contents) => match &checksum {
-                    Some(expected) => {
-                        let chksum = Sha256::digest(&contents);
-
-                        if chksum != *expected {
-                            tracing::warn!(
-                                "checksum mismatch, expected {} != actual {}",
-                                expected,
-                                chksum
-                            );
-                        } else {
-                            progress.inc_length(contents.len() as u64);
-                            progress.inc(contents.len() as u64);
-                            return Ok(contents.into());
-                        }
-                    }
-                    None => {
-                        progress.inc_length(contents.len() as u64);
-                        progress.inc(contents.len() as u64);
-                        return Ok(contents.into());
-                    }
-                },
-                Err(e) => {
-                    tracing::warn!(error = %e, "failed to read cached file");
+        let blob_path = crate::cache::blob_path(&dl_dir, &expected);
+
+        // Different payloads can have the exact same contents, so ensure the
+        // same blob is never retrieved by more than one thread at a time
+        let blob_lock = self
+            .blob_locks
+            .lock()
+            .entry(expected.0)
+            .or_default()
+            .clone();
+        let _blob_guard = blob_lock.lock();
+        // Other processes sharing the cache can also be retrieving the blob,
+        // and they must not write to the same partial file
+        let _blob_file_lock = crate::cache::lock_blob(&dl_dir, &expected)?;
+
+        // Blobs are only ever moved into place after their checksum has been
+        // validated, so their existence is enough
+        if blob_path.exists() {
+            let len = std::fs::metadata(&blob_path).map_or(0, |md| md.len());
+            progress.inc_length(len);
+            progress.inc(len);
+        } else if !self.migrate_cached(short_path, &blob_path, &expected, &progress) {
+            self.download(
+                url.as_ref(),
+                short_path,
+                &blob_path,
+                Some(&expected),
+                &progress,
+            )?;
+        }
+
+        let size = std::fs::metadata(&blob_path)
+            .with_context(|| format!("failed to read metadata for {}", blob_path))?
+            .len();
+
+        let mut index = self.index.lock();
+        if index.insert(&expected, size, short_path) {
+            *index = crate::cache::Index::update(&dl_dir, |index| {
+                index.insert(&expected, size, short_path)
+            })?;
+        }
+
+        Ok(blob_path)
+    }
+
+    /// Caches created before blobs were stored by their checksum store files
+    /// by name, so move any valid file into the blob location rather than
+    /// retrieving it again
+    fn migrate_cached(
+        &self,
+        short_path: &Path,
+        blob_path: &Path,
+        expected: &Sha256,
+        progress: &indicatif::ProgressBar,
+    ) -> bool {
+        let cache_path = self.work_dir.join("dl").join(short_path);
+
+        if !cache_path.is_file() {
+            return false;
+        }
+
+        tracing::debug!("verifying existing cached dl file");
+
+        match std::fs::File::open(&cache_path).and_then(Sha256::digest_reader) {
+            Ok(chksum) => {
+                if chksum != *expected {
+                    tracing::warn!(
+                        "checksum mismatch, expected {} != actual {}",
+                        expected,
+                        chksum
+                    );
+                    return false;
                 }
             }
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to read cached file");
+                return false;
+            }
         }
 
-        let res = self.client.get(url.as_ref()).call()?;
+        let moved = blob_path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::rename(&cache_path, blob_path));
+
+        match moved {
+            Ok(()) => {
+                let len = std::fs::metadata(blob_path).map_or(0, |md| md.len());
+                progress.inc_length(len);
+                progress.inc(len);
+                true
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to move {} to {}", cache_path, blob_path);
+                false
+            }
+        }
+    }
 
-        let content_length = res
-            .header("content-length")
-            .and_then(|header| header.parse().ok())
-            .unwrap_or_default();
-        progress.inc_length(content_length);
+    /// Downloads the url to the specified path, retrying failed attempts, and
+    /// validating the contents against the checksum if one is specified
+    fn download(
+        &self,
+        url: &str,
+        short_path: &Path,
+        cache_path: &Path,
+        checksum: Option<&Sha256>,
+        progress: &indicatif::ProgressBar,
+    ) -> Result<(), Error> {
+        if self.offline {
+            anyhow::bail!(
+                "'{}' is not in the cache, and network access is disabled in offline mode",
+                short_path,
+            );
+        }
+
+        let url = self.rewrite_url(url);
+        let partial_path = PathBuf::from(format!("{}.partial", cache_path));
+
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut attempt = 0;
+        loop {
+            let permit = self
+                .max_concurrent_downloads
+                .map(|max| self.downloads.acquire(max.max(1)));
+            let res = self.download_partial(url.as_ref(), &partial_path, checksum, progress);
+            drop(permit);
+
+            match res {
+                Ok(()) => break,
+                Err(FetchError::Retry(err)) if attempt < self.retries => {
+                    // Exponential backoff, capped at ~30s
+                    let backoff = std::time::Duration::from_millis(500 << attempt.min(6));
+                    attempt += 1;
+
+                    tracing::warn!(
+                        url = url.as_ref(),
+                        error = %err,
+                        "download failed, retrying ({}/{}) in {:?}",
+                        attempt,
+                        self.retries,
+                        backoff
+                    );
+
+                    std::thread::sleep(backoff);
+                }
+                Err(FetchError::Retry(err) | FetchError::Fatal(err)) => {
+                    return Err(err.context(format!("failed to download {}", url)))
+                }
+            }
+        }
+
+        std::fs::rename(&partial_path, cache_path)
+            .with_context(|| format!("failed to move {} to {}", partial_path, cache_path))
+    }
+
+    /// Applies the first [`Mirror`] whose prefix matches the url, if any
+    pub fn rewrite_url<'u>(&self, url: &'u str) -> std::borrow::Cow<'u, str> {
+        match self
+            .mirrors
+            .iter()
+            .find_map(|mirror| Some((mirror, url.strip_prefix(mirror.from.as_str())?)))
+        {
+            Some((mirror, rest)) => {
+                tracing::debug!("rewriting {} to use mirror {}", url, mirror.to);
+                format!("{}{}", mirror.to, rest).into()
+            }
+            None => url.into(),
+        }
+    }
+
+    /// Downloads the url to the partial path, resuming from the end of the
+    /// partial file if one exists, the checksum is known, and the server
+    /// supports range requests
+    fn download_partial(
+        &self,
+        url: &str,
+        partial_path: &Path,
+        checksum: Option<&Sha256>,
+        progress: &indicatif::ProgressBar,
+    ) -> Result<(), FetchError> {
+        // Without a checksum there's no way to know if the partial file is for
+        // the same contents, eg. the channel manifest is updated in place, so
+        // always start over
+        let offset = if checksum.is_some() {
+            std::fs::metadata(partial_path).map_or(0, |md| md.len())
+        } else {
+            0
+        };
+
+        let res = self.fetcher.fetch(url, offset, checksum)?;
+        let content_length = res.content_length.unwrap_or_default();
+        let resumed = offset > 0 && res.resumed;
+
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+
+        let file = if resumed {
+            progress.inc_length(offset);
+            progress.inc(offset);
 
-        let body = bytes::BytesMut::with_capacity(content_length as usize);
+            // Hash the portion we already have so that we only need to hash
+            // the remainder as it is received
+            std::fs::File::open(partial_path)
+                .and_then(|partial| {
+                    std::io::copy(&mut std::io::Read::take(partial, offset), &mut hasher)
+                })
+                .and_then(|_| std::fs::OpenOptions::new().append(true).open(partial_path))
+        } else {
+            std::fs::File::create(partial_path)
+        }
+        .with_context(|| format!("failed to open {}", partial_path))
+        .map_err(FetchError::Fatal)?;
 
-        struct ProgressCopy {
-            progress: indicatif::ProgressBar,
-            inner: bytes::buf::Writer<bytes::BytesMut>,
+        progress.inc_length(content_length);
+
+        struct ProgressCopy<'pb> {
+            progress: &'pb indicatif::ProgressBar,
+            inner: std::io::BufWriter<std::fs::File>,
+            hasher: sha2::Sha256,
+            written: u64,
+            limiter: Option<(&'pb RateLimiter, u64)>,
         }
 
-        impl std::io::Write for ProgressCopy {
+        impl<'pb> std::io::Write for ProgressCopy<'pb> {
             fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-                self.progress.inc(buf.len() as u64);
-                self.inner.write(buf)
+                if let Some((limiter, rate)) = self.limiter {
+                    limiter.consume(rate, buf.len() as u64);
+                }
+
+                let written = self.inner.write(buf)?;
+                self.hasher.update(&buf[..written]);
+                self.progress.inc(written as u64);
+                self.written += written as u64;
+                Ok(written)
             }
 
             fn flush(&mut self) -> std::io::Result<()> {
@@ -154,34 +518,86 @@ impl Ctx {
             }
         }
 
-        use bytes::BufMut;
-
         let mut pc = ProgressCopy {
             progress,
-            inner: body.writer(),
+            inner: std::io::BufWriter::new(file),
+            hasher,
+            written: 0,
+            limiter: self.limit_rate.map(|rate| (&self.rate_limiter, rate)),
         };
 
-        std::io::copy(&mut res.into_reader(), &mut pc)?;
+        let mut body = res.body;
+        let copied = std::io::copy(&mut body, &mut pc).and_then(|_| {
+            use std::io::Write;
+            pc.flush()?;
+
+            // The connection can be closed before the entire body is received
+            // without the reader reporting an error
+            if content_length > 0 && pc.written < content_length {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "connection closed after receiving {} of {} bytes",
+                        pc.written, content_length
+                    ),
+                ))
+            } else {
+                Ok(())
+            }
+        });
+
+        if let Err(err) = copied {
+            // Remove the part of the length we won't be receiving in this attempt,
+            // and the resumed portion, since it will be added again on the next attempt
+            let resumed_len = if resumed { offset } else { 0 };
+            progress.set_length(
+                progress
+                    .length()
+                    .saturating_sub(content_length.saturating_sub(pc.written) + resumed_len),
+            );
+            progress.set_position(progress.position().saturating_sub(pc.written + resumed_len));
 
-        let body = pc.inner.into_inner().freeze();
+            return Err(FetchError::Retry(err.into()));
+        }
 
         if let Some(expected) = checksum {
-            let chksum = Sha256::digest(&body);
+            let chksum = Sha256(pc.hasher.finalize().into());
+
+            if chksum != *expected {
+                // The partial file might have been corrupted, so start over
+                // completely rather than attempting to resume again
+                let _ = std::fs::remove_file(partial_path);
+                let len = pc.written + if resumed { offset } else { 0 };
+                progress.set_length(progress.length().saturating_sub(len));
+                progress.set_position(progress.position().saturating_sub(len));
+
+                let err = anyhow::anyhow!(
+                    "checksum mismatch, expected {} != actual {}",
+                    expected,
+                    chksum
+                );
 
-            anyhow::ensure!(
-                chksum == expected,
-                "checksum mismatch, expected {} != actual {}",
-                expected,
-                chksum
-            );
+                // If the entire contents were received in this attempt then
+                // they are just not what was pinned, and retrieving them again
+                // won't change that
+                return Err(if resumed {
+                    FetchError::Retry(err)
+                } else {
+                    FetchError::Fatal(err)
+                });
+            }
         }
 
-        if let Some(parent) = cache_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
+        Ok(())
+    }
 
-        std::fs::write(cache_path, &body)?;
-        Ok(body)
+    /// Creates a dedicated pool rather than using the global one, so that the
+    /// number of threads can be limited via `jobs`
+    pub(crate) fn thread_pool(&self) -> Result<rayon::ThreadPool, Error> {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs.unwrap_or_default())
+            .build()
+            .context("failed to create thread pool")
     }
 
     pub fn execute(
@@ -191,6 +607,18 @@ impl Ctx {
         arches: u32,
         variants: u32,
         ops: crate::Ops,
+    ) -> Result<(), Error> {
+        self.thread_pool()?
+            .install(|| self.execute_ops(packages, payloads, arches, variants, ops))
+    }
+
+    fn execute_ops(
+        self: std::sync::Arc<Self>,
+        packages: std::collections::BTreeMap<String, crate::manifest::ManifestItem>,
+        payloads: Vec<WorkItem>,
+        arches: u32,
+        variants: u32,
+        ops: crate::Ops,
     ) -> Result<(), Error> {
         use rayon::prelude::*;
 
@@ -213,6 +641,10 @@ impl Ctx {
                 let payload_contents =
                     crate::download::download(self.clone(), packages.clone(), &wi)?;
 
+                if let Some(roots) = &self.signature_roots {
+                    crate::authenticode::verify(&wi, &payload_contents, roots)?;
+                }
+
                 if let crate::Ops::Download = ops {
                     return Ok(None);
                 }
@@ -229,6 +661,7 @@ impl Ctx {
                         splat_roots.as_ref().unwrap(),
                         &wi,
                         ft,
+                        &self.unpack_filter,
                         arches,
                         variants,
                     )
@@ -265,7 +698,10 @@ impl Ctx {
 
         if let Ok(unpack) = std::fs::read(&unpack_dir) {
             if let Ok(um) = serde_json::from_slice::<crate::unpack::UnpackMeta>(&unpack) {
-                if payload.sha256 == um.sha256 {
+                if payload.sha256 == um.sha256
+                    && um.include == self.unpack_filter.include
+                    && um.exclude == self.unpack_filter.exclude
+                {
                     tracing::debug!("already unpacked");
                     unpack_dir.pop();
                     return Ok(Unpack::Present {
@@ -322,3 +758,218 @@ impl Drop for Ctx {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn custom_fetcher() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = Path::from_path(td.path()).unwrap();
+        std::fs::write(root.join("a.vsix"), b"contents").unwrap();
+
+        let mut ctx = Ctx::with_temp(ProgressTarget::Hidden).unwrap();
+        ctx.fetcher = Box::new(crate::fetch::DirFetcher::new(root));
+        ctx.retries = 0;
+
+        let contents = ctx
+            .get_and_validate(
+                "https://example.com/a.vsix",
+                &"a.vsix",
+                Some(Sha256::digest(b"contents")),
+                indicatif::ProgressBar::hidden(),
+            )
+            .unwrap();
+        assert_eq!(&contents[..], b"contents");
+
+        assert!(ctx
+            .get_and_validate(
+                "https://example.com/a.vsix",
+                &"b.vsix",
+                Some(Sha256([0; 32])),
+                indicatif::ProgressBar::hidden(),
+            )
+            .is_err());
+    }
+
+    /// Retrieves the same contents for every url, slowly
+    struct SlowFetcher {
+        contents: Vec<u8>,
+        fetches: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl crate::Fetcher for SlowFetcher {
+        fn fetch(
+            &self,
+            _url: &str,
+            _offset: u64,
+            _checksum: Option<&Sha256>,
+        ) -> Result<crate::FetchResponse, FetchError> {
+            struct Slow(std::io::Cursor<Vec<u8>>);
+
+            impl std::io::Read for Slow {
+                fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                    let len = buf.len().min(4);
+                    self.0.read(&mut buf[..len])
+                }
+            }
+
+            self.fetches
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+            Ok(crate::FetchResponse {
+                body: Box::new(Slow(std::io::Cursor::new(self.contents.clone()))),
+                content_length: Some(self.contents.len() as u64),
+                resumed: false,
+            })
+        }
+    }
+
+    #[test]
+    fn shared_cache() {
+        use std::sync::{atomic::AtomicUsize, Arc, Barrier};
+
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let contents = b"shared blob contents".repeat(4);
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(2));
+
+        // Each context acts as a separate process sharing the same cache
+        let downloaders: Vec<_> = (0..2)
+            .map(|_| {
+                let root = root.clone();
+                let contents = contents.clone();
+                let fetches = fetches.clone();
+                let barrier = barrier.clone();
+
+                std::thread::spawn(move || {
+                    let mut ctx = Ctx::with_dir(root, ProgressTarget::Hidden).unwrap();
+                    ctx.fetcher = Box::new(SlowFetcher {
+                        contents: contents.clone(),
+                        fetches,
+                    });
+
+                    barrier.wait();
+                    let path = ctx
+                        .get_and_validate_path(
+                            "https://example.com/a.cab",
+                            &"a.cab",
+                            Some(Sha256::digest(&contents)),
+                            indicatif::ProgressBar::hidden(),
+                        )
+                        .unwrap();
+                    std::fs::read(path).unwrap()
+                })
+            })
+            .collect();
+
+        for downloader in downloaders {
+            assert_eq!(downloader.join().unwrap(), contents);
+        }
+
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        use std::sync::{atomic::AtomicUsize, Arc};
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let mut ctx = Ctx::with_temp(ProgressTarget::Hidden).unwrap();
+        ctx.fetcher = Box::new(SlowFetcher {
+            contents: b"modified".to_vec(),
+            fetches: fetches.clone(),
+        });
+        ctx.retries = 3;
+
+        let err = ctx
+            .get_and_validate_path(
+                "https://example.com/a.cab",
+                &"a.cab",
+                Some(Sha256::digest(b"pinned")),
+                indicatif::ProgressBar::hidden(),
+            )
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("checksum mismatch"));
+
+        // The contents are not retrieved again, as they won't match either
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn offline() {
+        use std::sync::{atomic::AtomicUsize, Arc};
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let mut ctx = Ctx::with_temp(ProgressTarget::Hidden).unwrap();
+        ctx.fetcher = Box::new(SlowFetcher {
+            contents: b"fetched".to_vec(),
+            fetches: fetches.clone(),
+        });
+        ctx.offline = true;
+
+        let err = ctx
+            .get_and_validate_path(
+                "https://example.com/a.cab",
+                &"a.cab",
+                Some(Sha256::digest(b"missing")),
+                indicatif::ProgressBar::hidden(),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("'a.cab' is not in the cache"));
+
+        // Blobs that are already present are still used
+        let cached = b"cached contents";
+        let blob = crate::cache::blob_path(&ctx.work_dir.join("dl"), &Sha256::digest(cached));
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(&blob, cached).unwrap();
+
+        let path = ctx
+            .get_and_validate_path(
+                "https://example.com/b.cab",
+                &"b.cab",
+                Some(Sha256::digest(cached)),
+                indicatif::ProgressBar::hidden(),
+            )
+            .unwrap();
+        assert_eq!(path, blob);
+        assert_eq!(std::fs::read(path).unwrap(), cached);
+
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn mirrors() {
+        assert!("https://example.com".parse::<Mirror>().is_err());
+        assert!("=https://example.com".parse::<Mirror>().is_err());
+
+        let mut ctx = Ctx::with_temp(ProgressTarget::Hidden).unwrap();
+        ctx.mirrors = vec![
+            "https://aka.ms/vs/=https://mirror.example.com/channels/"
+                .parse()
+                .unwrap(),
+            "https://download.visualstudio.microsoft.com/=https://mirror.example.com/vs/"
+                .parse()
+                .unwrap(),
+        ];
+
+        assert_eq!(
+            ctx.rewrite_url("https://aka.ms/vs/17/release/channel"),
+            "https://mirror.example.com/channels/17/release/channel"
+        );
+        assert_eq!(
+            ctx.rewrite_url("https://download.visualstudio.microsoft.com/download/pr/a/b.msi"),
+            "https://mirror.example.com/vs/download/pr/a/b.msi"
+        );
+        assert_eq!(
+            ctx.rewrite_url("https://example.com/b.msi"),
+            "https://example.com/b.msi"
+        );
+    }
+}
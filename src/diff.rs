@@ -0,0 +1,105 @@
+use crate::Payload;
+
+/// A change to a selected payload between two package manifests
+#[derive(Debug)]
+pub enum Change<'p> {
+    /// The payload is only selected in the new manifest
+    Added(&'p Payload),
+    /// The payload is only selected in the old manifest
+    Removed(&'p Payload),
+    /// The payload is selected in both manifests, but its contents differ
+    Changed { old: &'p Payload, new: &'p Payload },
+}
+
+/// Payload filenames include the versions of the CRT or SDK they are part of,
+/// so strip them to be able to match the same payload across versions
+fn payload_key(versions: &regex::Regex, payload: &Payload) -> String {
+    let key = versions.replace_all(payload.filename.as_str(), "*");
+
+    // The SDK package family changes between Windows 10 and 11
+    key.replacen("Win10SDK_", "WinSDK_", 1)
+        .replacen("Win11SDK_", "WinSDK_", 1)
+}
+
+/// Compares the payloads selected from two different manifests, payloads that
+/// are identical in both are not included in the output
+pub fn diff_payloads<'p>(old: &'p [Payload], new: &'p [Payload]) -> Vec<Change<'p>> {
+    let versions = regex::Regex::new(r"\d+(\.\d+)+").unwrap();
+
+    let mut new: Vec<_> = new
+        .iter()
+        .map(|pay| (payload_key(&versions, pay), pay))
+        .collect();
+    let mut changes = Vec::new();
+
+    for old in old {
+        let key = payload_key(&versions, old);
+
+        match new.iter().position(|(nkey, _)| *nkey == key) {
+            Some(i) => {
+                let (_, new) = new.remove(i);
+
+                if old.sha256 != new.sha256 || old.filename != new.filename {
+                    changes.push(Change::Changed { old, new });
+                }
+            }
+            None => changes.push(Change::Removed(old)),
+        }
+    }
+
+    changes.extend(new.into_iter().map(|(_, new)| Change::Added(new)));
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{util::Sha256, PayloadKind};
+
+    fn payload(filename: &str, fill: u8) -> Payload {
+        Payload {
+            filename: filename.into(),
+            sha256: Sha256([fill; 32]),
+            url: String::new(),
+            size: fill as u64,
+            install_size: None,
+            kind: PayloadKind::SdkHeaders,
+            target_arch: None,
+            variant: None,
+        }
+    }
+
+    #[test]
+    fn diffs() {
+        let old = [
+            payload("ucrt.msi", 1),
+            payload("Win10SDK_10.0.19041_headers.msi", 2),
+            payload("Win10SDK_10.0.19041_store_headers.msi", 3),
+            payload("Microsoft.VC.14.29.16.10.CRT.Headers.base.vsix", 4),
+        ];
+
+        let new = [
+            payload("ucrt.msi", 1),
+            payload("Win11SDK_10.0.22000_headers.msi", 5),
+            payload("Win11SDK_10.0.22000_store_headers_1.msi", 6),
+            payload("Win11SDK_10.0.22000_store_headers.msi", 3),
+            payload("Microsoft.VC.14.29.16.10.CRT.Headers.base.vsix", 4),
+        ];
+
+        let changes = diff_payloads(&old, &new);
+        assert_eq!(changes.len(), 3);
+
+        assert!(matches!(
+            changes[0],
+            Change::Changed { old, new } if old.sha256 != new.sha256
+        ));
+        // Same contents, but the name changed
+        assert!(matches!(
+            changes[1],
+            Change::Changed { old, new } if old.sha256 == new.sha256
+        ));
+        assert!(
+            matches!(changes[2], Change::Added(new) if new.filename == "Win11SDK_10.0.22000_store_headers_1.msi")
+        );
+    }
+}
@@ -4,24 +4,25 @@ use camino::Utf8PathBuf as PathBuf;
 use std::sync::Arc;
 
 #[derive(Debug)]
-struct Cab {
-    filename: PathBuf,
-    sha256: Sha256,
-    url: String,
-    #[allow(dead_code)]
-    size: u64,
+pub(crate) struct Cab {
+    pub(crate) filename: PathBuf,
+    pub(crate) sha256: Sha256,
+    pub(crate) url: String,
+    pub(crate) size: u64,
 }
 
 pub(crate) struct CabContents {
     pub(crate) path: PathBuf,
-    pub(crate) content: bytes::Bytes,
-    pub(crate) sequence: u32,
+    /// The location of the CAB in the download cache
+    pub(crate) content: PathBuf,
 }
 
+/// The downloaded payload contents, which are kept on disk in the download
+/// cache rather than in memory as they can be quite large
 pub(crate) enum PayloadContents {
-    Vsix(bytes::Bytes),
+    Vsix(PathBuf),
     Msi {
-        msi: bytes::Bytes,
+        msi: PathBuf,
         cabs: Vec<CabContents>,
     },
 }
@@ -33,7 +34,7 @@ pub(crate) fn download(
 ) -> Result<PayloadContents, Error> {
     item.progress.set_message("📥 downloading..");
 
-    let contents = ctx.get_and_validate(
+    let contents = ctx.get_and_validate_path(
         &item.payload.url,
         &item.payload.filename,
         Some(item.payload.sha256.clone()),
@@ -42,32 +43,7 @@ pub(crate) fn download(
 
     let pc = match item.payload.filename.extension() {
         Some("msi") => {
-            let cabs: Vec<_> = match pkgs.values().find(|mi| {
-                mi.payloads
-                    .iter()
-                    .any(|mi_payload| mi_payload.sha256 == item.payload.sha256)
-            }) {
-                Some(mi) => mi
-                    .payloads
-                    .iter()
-                    .filter_map(|pay| {
-                        pay.file_name.ends_with(".cab").then(|| Cab {
-                            filename: pay
-                                .file_name
-                                .strip_prefix("Installers\\")
-                                .unwrap_or(&pay.file_name)
-                                .into(),
-                            sha256: pay.sha256.clone(),
-                            url: pay.url.clone(),
-                            size: pay.size,
-                        })
-                    })
-                    .collect(),
-                None => anyhow::bail!(
-                    "unable to find manifest parent for {}",
-                    item.payload.filename
-                ),
-            };
+            let cabs = find_cabs(&pkgs, &item.payload)?;
 
             download_cabs(ctx, &cabs, item, contents)
         }
@@ -80,25 +56,49 @@ pub(crate) fn download(
     pc
 }
 
-/// Each SDK MSI has 1 or more cab files associated with it containing the actual
-/// data we need that must be downloaded separately and indexed from the MSI
-fn download_cabs(
-    ctx: Arc<Ctx>,
-    cabs: &[Cab],
-    msi: &crate::WorkItem,
-    msi_content: bytes::Bytes,
-) -> Result<PayloadContents, Error> {
-    use rayon::prelude::*;
-
-    let msi_filename = &msi.payload.filename;
+/// Finds all of the CAB files in the same manifest item as the specified MSI,
+/// note that this is a superset of the CABs that the MSI actually references
+pub(crate) fn find_cabs(
+    pkgs: &std::collections::BTreeMap<String, manifest::ManifestItem>,
+    msi: &crate::Payload,
+) -> Result<Vec<Cab>, Error> {
+    let mi = pkgs
+        .values()
+        .find(|mi| {
+            mi.payloads
+                .iter()
+                .any(|mi_payload| mi_payload.sha256 == msi.sha256)
+        })
+        .with_context(|| format!("unable to find manifest parent for {}", msi.filename))?;
 
-    let mut msi_pkg = msi::Package::open(std::io::Cursor::new(msi_content.clone()))
-        .with_context(|| format!("invalid MSI for {}", msi_filename))?;
+    Ok(mi
+        .payloads
+        .iter()
+        .filter_map(|pay| {
+            pay.file_name.ends_with(".cab").then(|| Cab {
+                filename: pay
+                    .file_name
+                    .strip_prefix("Installers\\")
+                    .unwrap_or(&pay.file_name)
+                    .into(),
+                sha256: pay.sha256.clone(),
+                url: pay.url.clone(),
+                size: pay.size,
+            })
+        })
+        .collect())
+}
 
+/// Finds the CABs that are actually referenced by the MSI
+fn referenced_cabs<'cabs, R: std::io::Read + std::io::Seek>(
+    msi_pkg: &mut msi::Package<R>,
+    msi_filename: &camino::Utf8Path,
+    cabs: &'cabs [Cab],
+) -> Result<Vec<&'cabs Cab>, Error> {
     // The `Media` table contains the list of cabs by name, which we then need
     // to lookup in the list of payloads.
     // Columns: [DiskId, LastSequence, DiskPrompt, Cabinet, VolumeLabel, Source]
-    let cab_files: Vec<_> = msi_pkg
+    Ok(msi_pkg
         .select_rows(msi::Select::table("Media"))
         .with_context(|| format!("{} does not contain a list of CAB files", msi_filename))?
         .filter_map(|row| {
@@ -111,46 +111,116 @@ fn download_cabs(
             if row.len() >= 3 {
                 // For some reason most/all of the msi files contain a NULL cabinet
                 // in the first position which is useless
-                row[3]
-                    .as_str()
-                    .and_then(|s| row[1].as_int().map(|seq| (s, seq as u32)))
-                    .and_then(|(name, seq)| {
-                        let cab_name = name.trim_matches('"');
-
-                        cabs.iter().find_map(|payload| {
-                            (payload.filename == cab_name).then(|| {
-                                (
-                                    PathBuf::from(format!(
-                                        "{}/{}",
-                                        msi_filename.file_stem().unwrap(),
-                                        cab_name
-                                    )),
-                                    payload.sha256.clone(),
-                                    payload.url.clone(),
-                                    seq,
-                                )
-                            })
-                        })
-                    })
+                row[3].as_str().and_then(|name| {
+                    let cab_name = name.trim_matches('"');
+                    cabs.iter().find(|cab| cab.filename == cab_name)
+                })
             } else {
                 None
             }
         })
+        .collect())
+}
+
+/// Downloads each MSI payload, without any of its CABs, to determine the CABs
+/// it actually references, as well as its exact install size from the sizes
+/// of the files in its `File` table that are actually unpacked
+pub fn resolve_cabs(
+    ctx: &Ctx,
+    pkg_manifest: &manifest::PackageManifest,
+    payloads: Vec<crate::Payload>,
+    progress: indicatif::ProgressBar,
+) -> Result<Vec<crate::lock::LockedPayload>, Error> {
+    use rayon::prelude::*;
+
+    let resolve = |mut payload: crate::Payload| -> Result<_, Error> {
+        if payload.filename.extension() != Some("msi") {
+            return Ok(crate::lock::LockedPayload {
+                payload,
+                cabs: Vec::new(),
+            });
+        }
+
+        let cabs = find_cabs(&pkg_manifest.packages, &payload)?;
+
+        let msi_content = ctx.get_and_validate_path(
+            &payload.url,
+            &payload.filename,
+            Some(payload.sha256.clone()),
+            progress.clone(),
+        )?;
+
+        let mut msi_pkg = std::fs::File::open(&msi_content)
+            .map_err(Error::from)
+            .and_then(|file| Ok(msi::Package::open(std::io::BufReader::new(file))?))
+            .with_context(|| format!("invalid MSI for {}", payload.filename))?;
+
+        let install_size =
+            crate::unpack::read_msi_files(&mut msi_pkg, &payload.filename, &ctx.unpack_filter)?
+                .iter()
+                .map(|file| file.size)
+                .sum();
+        payload.install_size = Some(install_size);
+
+        let cabs = referenced_cabs(&mut msi_pkg, &payload.filename, &cabs)?
+            .into_iter()
+            .map(|cab| crate::lock::LockedCab {
+                filename: cab.filename.clone(),
+                url: cab.url.clone(),
+                sha256: cab.sha256.clone(),
+                size: cab.size,
+            })
+            .collect();
+
+        Ok(crate::lock::LockedPayload { payload, cabs })
+    };
+
+    ctx.thread_pool()?
+        .install(|| payloads.into_par_iter().map(resolve).collect())
+}
+
+/// Each SDK MSI has 1 or more cab files associated with it containing the actual
+/// data we need that must be downloaded separately and indexed from the MSI
+fn download_cabs(
+    ctx: Arc<Ctx>,
+    cabs: &[Cab],
+    msi: &crate::WorkItem,
+    msi_content: PathBuf,
+) -> Result<PayloadContents, Error> {
+    use rayon::prelude::*;
+
+    let msi_filename = &msi.payload.filename;
+
+    let mut msi_pkg = std::fs::File::open(&msi_content)
+        .map_err(Error::from)
+        .and_then(|file| Ok(msi::Package::open(std::io::BufReader::new(file))?))
+        .with_context(|| format!("invalid MSI for {}", msi_filename))?;
+
+    let cab_files: Vec<_> = referenced_cabs(&mut msi_pkg, msi_filename, cabs)?
+        .into_iter()
+        .map(|cab| {
+            (
+                PathBuf::from(format!(
+                    "{}/{}",
+                    msi_filename.file_stem().unwrap(),
+                    cab.filename
+                )),
+                cab.sha256.clone(),
+                cab.url.clone(),
+            )
+        })
         .collect();
 
     let cabs = cab_files
         .into_par_iter()
-        .map(
-            |(cab_name, chksum, url, sequence)| -> Result<CabContents, Error> {
-                let cab_contents =
-                    ctx.get_and_validate(&url, &cab_name, Some(chksum), msi.progress.clone())?;
-                Ok(CabContents {
-                    path: cab_name,
-                    content: cab_contents,
-                    sequence,
-                })
-            },
-        )
+        .map(|(cab_name, chksum, url)| -> Result<CabContents, Error> {
+            let cab_contents =
+                ctx.get_and_validate_path(&url, &cab_name, Some(chksum), msi.progress.clone())?;
+            Ok(CabContents {
+                path: cab_name,
+                content: cab_contents,
+            })
+        })
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(PayloadContents::Msi {
@@ -158,3 +228,204 @@ fn download_cabs(
         cabs,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Creates an MSI with files in 2 CABs
+    fn create_msi() -> Vec<u8> {
+        use msi::{Column, Insert, Value};
+
+        let mut msi_pkg = msi::Package::create(
+            msi::PackageType::Installer,
+            std::io::Cursor::new(Vec::new()),
+        )
+        .unwrap();
+
+        msi_pkg
+            .create_table(
+                "Media",
+                vec![
+                    Column::build("DiskId").primary_key().int16(),
+                    Column::build("LastSequence").int32(),
+                    Column::build("DiskPrompt").nullable().text_string(64),
+                    Column::build("Cabinet").nullable().string(255),
+                ],
+            )
+            .unwrap();
+        msi_pkg
+            .insert_rows(Insert::into("Media").rows(vec![
+                vec![Value::Int(1), Value::Int(0), Value::Null, Value::Null],
+                vec![
+                    Value::Int(2),
+                    Value::Int(1),
+                    Value::Null,
+                    Value::from("a.cab"),
+                ],
+                vec![
+                    Value::Int(3),
+                    Value::Int(2),
+                    Value::Null,
+                    Value::from("b.cab"),
+                ],
+            ]))
+            .unwrap();
+
+        msi_pkg
+            .create_table(
+                "Directory",
+                vec![
+                    Column::build("Directory").primary_key().id_string(72),
+                    Column::build("Directory_Parent").nullable().id_string(72),
+                    Column::build("DefaultDir").string(255),
+                ],
+            )
+            .unwrap();
+        msi_pkg
+            .insert_rows(Insert::into("Directory").rows(vec![
+                vec![
+                    Value::from("TARGETDIR"),
+                    Value::Null,
+                    Value::from("SourceDir"),
+                ],
+                vec![
+                    Value::from("KITS"),
+                    Value::from("TARGETDIR"),
+                    Value::from("Windows Kits"),
+                ],
+                vec![Value::from("LIB"), Value::from("KITS"), Value::from("Lib")],
+                vec![Value::from("BIN"), Value::from("KITS"), Value::from("bin")],
+            ]))
+            .unwrap();
+
+        msi_pkg
+            .create_table(
+                "Component",
+                vec![
+                    Column::build("Component").primary_key().id_string(72),
+                    Column::build("ComponentId").nullable().string(38),
+                    Column::build("Directory_").id_string(72),
+                ],
+            )
+            .unwrap();
+        msi_pkg
+            .insert_rows(Insert::into("Component").rows(vec![
+                vec![Value::from("lib"), Value::Null, Value::from("LIB")],
+                vec![Value::from("bin"), Value::Null, Value::from("BIN")],
+            ]))
+            .unwrap();
+
+        msi_pkg
+            .create_table(
+                "File",
+                vec![
+                    Column::build("File").primary_key().id_string(72),
+                    Column::build("Component_").id_string(72),
+                    Column::build("FileName").string(255),
+                    Column::build("FileSize").int32(),
+                    Column::build("Version").nullable().string(72),
+                    Column::build("Language").nullable().string(20),
+                    Column::build("Attributes").nullable().int16(),
+                    Column::build("Sequence").int16(),
+                ],
+            )
+            .unwrap();
+
+        let file = |id: &str, component: &str, size: i32, sequence: i32| {
+            vec![
+                Value::from(id),
+                Value::from(component),
+                Value::from(id),
+                Value::Int(size),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Int(sequence),
+            ]
+        };
+
+        // The tool in bin is never unpacked, so doesn't count toward the install size
+        msi_pkg
+            .insert_rows(Insert::into("File").rows(vec![
+                file("a.lib", "lib", 1000, 1),
+                file("b.lib", "lib", 234, 2),
+                file("c.exe", "bin", 5000, 2),
+            ]))
+            .unwrap();
+
+        msi_pkg.into_inner().unwrap().into_inner()
+    }
+
+    #[test]
+    fn resolves_cabs() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = camino::Utf8Path::from_path(td.path()).unwrap();
+
+        let msi = create_msi();
+        let msi_sha = Sha256::digest(&msi);
+        let blob = crate::cache::blob_path(root, &msi_sha);
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(&blob, &msi).unwrap();
+
+        let payload = |file_name: &str, sha256: &Sha256, size: usize| {
+            serde_json::json!({
+                "fileName": file_name,
+                "sha256": sha256.to_string(),
+                "size": size,
+                "url": format!("https://example.com/{}", file_name),
+            })
+        };
+
+        // The CABs themselves are never retrieved
+        let pkg_manifest = manifest::PackageManifest::from_slice(
+            &serde_json::to_vec(&serde_json::json!({
+                "packages": [{
+                    "id": "Win10SDK",
+                    "version": "10.0.19041.5",
+                    "type": "Msi",
+                    "payloads": [
+                        payload("Installers\\x.msi", &msi_sha, msi.len()),
+                        payload("Installers\\a.cab", &Sha256([1; 32]), 10),
+                        payload("Installers\\b.cab", &Sha256([2; 32]), 20),
+                        payload("Installers\\unused.cab", &Sha256([3; 32]), 30),
+                    ],
+                }],
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mut ctx = Ctx::with_temp(crate::util::ProgressTarget::Hidden).unwrap();
+        ctx.fetcher = Box::new(crate::fetch::DirFetcher::new(root));
+        ctx.retries = 0;
+
+        let resolved = resolve_cabs(
+            &ctx,
+            &pkg_manifest,
+            vec![crate::Payload {
+                filename: "x.msi".into(),
+                sha256: msi_sha,
+                url: "https://example.com/Installers/x.msi".to_owned(),
+                size: msi.len() as u64,
+                install_size: None,
+                kind: crate::PayloadKind::SdkLibs,
+                target_arch: None,
+                variant: None,
+            }],
+            indicatif::ProgressBar::hidden(),
+        )
+        .unwrap();
+
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].payload.install_size, Some(1234));
+        assert_eq!(
+            resolved[0]
+                .cabs
+                .iter()
+                .map(|cab| (cab.filename.as_str(), cab.size))
+                .collect::<Vec<_>>(),
+            [("a.cab", 10), ("b.cab", 20)]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Retrieval of the contents of urls is done via the [`Fetcher`] trait, so
+//! that library users can route requests through their own client, or serve
+//! them locally, eg. in tests. [`crate::HttpClient`] is used by default.
+
+use crate::{util::Sha256, Error, Path, PathBuf};
+use anyhow::Context as _;
+
+/// The (possibly partial) contents of a url
+pub struct FetchResponse {
+    /// The body of the response
+    pub body: Box<dyn std::io::Read + Send>,
+    /// The length of the body, if known
+    pub content_length: Option<u64>,
+    /// True if the body starts at the requested offset, false if it starts
+    /// at the beginning of the contents
+    pub resumed: bool,
+}
+
+/// A failure to retrieve a url
+#[derive(Debug)]
+pub enum FetchError {
+    /// The failure is (possibly) transient, eg. a network error, so the
+    /// request can be retried
+    Retry(Error),
+    /// The failure will happen again no matter how many times it is retried
+    Fatal(Error),
+}
+
+/// Retrieves the contents of urls. Note that the contents are always
+/// validated against the expected checksum, if there is one, regardless of
+/// the implementation
+pub trait Fetcher: Send + Sync {
+    /// Starts retrieving the contents of the url, skipping the first `offset`
+    /// bytes if possible, as they have already been retrieved by a previous
+    /// attempt. The expected checksum, if known, is also provided for
+    /// implementations that can retrieve contents by their checksum.
+    fn fetch(
+        &self,
+        url: &str,
+        offset: u64,
+        checksum: Option<&Sha256>,
+    ) -> Result<FetchResponse, FetchError>;
+}
+
+/// Opens a local file as a response, skipping to the offset if possible
+pub(crate) fn open_file(path: &Path, offset: u64) -> Result<FetchResponse, FetchError> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open {}", path))
+        .map_err(FetchError::Fatal)?;
+    let len = file
+        .metadata()
+        .with_context(|| format!("failed to read metadata for {}", path))
+        .map_err(FetchError::Fatal)?
+        .len();
+
+    let resumed = offset > 0 && offset <= len;
+    if resumed {
+        file.seek(SeekFrom::Start(offset))
+            .with_context(|| format!("failed to seek in {}", path))
+            .map_err(FetchError::Fatal)?;
+    }
+
+    Ok(FetchResponse {
+        body: Box::new(file),
+        content_length: Some(if resumed { len - offset } else { len }),
+        resumed,
+    })
+}
+
+/// Retrieves `file://` urls from the local filesystem
+pub struct FileFetcher;
+
+impl FileFetcher {
+    /// Gets the path of a `file://` url, or `None` if it is not a file url
+    pub fn path(url: &str) -> Option<PathBuf> {
+        let path = url.strip_prefix("file://")?;
+
+        // Windows paths are specified as `file:///C:/...`
+        #[cfg(windows)]
+        let path = path.strip_prefix('/').unwrap_or(path);
+
+        Some(PathBuf::from(path))
+    }
+}
+
+impl Fetcher for FileFetcher {
+    fn fetch(
+        &self,
+        url: &str,
+        offset: u64,
+        _checksum: Option<&Sha256>,
+    ) -> Result<FetchResponse, FetchError> {
+        let path = Self::path(url)
+            .with_context(|| format!("'{}' is not a file url", url))
+            .map_err(FetchError::Fatal)?;
+
+        open_file(&path, offset)
+    }
+}
+
+/// Retrieves every url from a local directory, where the contents are
+/// either stored by their checksum, as in xwin's own download cache, eg.
+/// `<root>/sha256/<checksum>`, or by the path of the url, eg.
+/// `https://example.com/a/b.msi` is retrieved from `<root>/a/b.msi`
+pub struct DirFetcher {
+    root: PathBuf,
+}
+
+impl DirFetcher {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl Fetcher for DirFetcher {
+    fn fetch(
+        &self,
+        url: &str,
+        offset: u64,
+        checksum: Option<&Sha256>,
+    ) -> Result<FetchResponse, FetchError> {
+        if let Some(checksum) = checksum {
+            let blob = crate::cache::blob_path(&self.root, checksum);
+            if blob.exists() {
+                return open_file(&blob, offset);
+            }
+        }
+
+        let url_path = url
+            .split_once("://")
+            .and_then(|(_, rest)| rest.split_once('/'))
+            .map(|(_, path)| path.split(['?', '#']).next().unwrap_or(path))
+            .with_context(|| format!("unable to determine the path of '{}'", url))
+            .map_err(FetchError::Fatal)?;
+
+        open_file(&self.root.join(url_path), offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(res: FetchResponse) -> (Vec<u8>, bool) {
+        let mut body = Vec::new();
+        let mut reader = res.body;
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(res.content_length, Some(body.len() as u64));
+        (body, res.resumed)
+    }
+
+    #[test]
+    fn local() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = Path::from_path(td.path()).unwrap();
+
+        std::fs::create_dir_all(root.join("download/pr")).unwrap();
+        std::fs::write(root.join("download/pr/a.msi"), b"by path").unwrap();
+
+        let checksum = Sha256([2; 32]);
+        let blob = crate::cache::blob_path(root, &checksum);
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(&blob, b"by checksum").unwrap();
+
+        let dir = DirFetcher::new(root);
+        let url = "https://download.example.com/download/pr/a.msi?x=1";
+
+        assert_eq!(
+            read(dir.fetch(url, 0, None).unwrap()),
+            (b"by path".to_vec(), false)
+        );
+        assert_eq!(
+            read(dir.fetch(url, 3, Some(&checksum)).unwrap()),
+            (b"checksum".to_vec(), true)
+        );
+        assert!(matches!(
+            dir.fetch("https://example.com/missing.msi", 0, None),
+            Err(FetchError::Fatal(_))
+        ));
+
+        let file_url = format!("file://{}", root.join("download/pr/a.msi"));
+        assert_eq!(
+            read(FileFetcher.fetch(&file_url, 100, None).unwrap()),
+            (b"by path".to_vec(), false)
+        );
+    }
+}
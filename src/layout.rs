@@ -0,0 +1,283 @@
+//! Support for using an existing Visual Studio offline layout, ie. a directory
+//! created via `vs_buildtools.exe --layout <dir>`, instead of retrieving the
+//! manifests and payloads from Microsoft
+//!
+//! A layout contains the package manifest as `Catalog.json`, and the payloads
+//! of each package in a `<id>,version=<version>[,chip=<chip>][,language=<lang>]`
+//! directory.
+
+use crate::{
+    fetch::{self, FetchError, FetchResponse},
+    manifest::{Chip, PackageManifest},
+    util::Sha256,
+    Error, Path, PathBuf,
+};
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+
+/// Reads the package manifest from the layout's `Catalog.json`
+pub fn read_catalog(layout_dir: &Path) -> Result<PackageManifest, Error> {
+    let catalog_path = layout_dir.join("Catalog.json");
+    let catalog = std::fs::read(&catalog_path)
+        .with_context(|| format!("failed to read layout catalog '{}'", catalog_path))?;
+
+    let pkg_manifest = PackageManifest::from_slice(&catalog)
+        .with_context(|| format!("failed to parse layout catalog '{}'", catalog_path))?;
+    if let Some(sha256) = &pkg_manifest.sha256 {
+        tracing::info!("package manifest sha256: {}", sha256);
+    }
+
+    Ok(pkg_manifest)
+}
+
+/// A package directory in the layout
+struct PackageDir {
+    path: PathBuf,
+    version: String,
+    chip: Option<String>,
+}
+
+/// Parses a package directory name, eg. `Win10SDK_10.0.19041,version=10.0.19041.5,chip=x64`
+fn parse_dir_name(name: &str) -> Option<(String, String, Option<String>)> {
+    let mut parts = name.split(',');
+    let id = parts.next()?;
+
+    let mut version = None;
+    let mut chip = None;
+
+    for part in parts {
+        match part.split_once('=') {
+            Some(("version", v)) => version = Some(v),
+            Some(("chip", c)) => chip = Some(c.to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+
+    Some((id.to_ascii_lowercase(), version?.to_owned(), chip))
+}
+
+/// Retrieves payloads from the files in the layout that match their checksum,
+/// rather than downloading them
+pub struct LayoutFetcher {
+    /// The possible locations of each payload in the layout, keyed by its
+    /// checksum
+    payloads: BTreeMap<[u8; 32], Vec<PathBuf>>,
+}
+
+impl LayoutFetcher {
+    /// Resolves the location of every payload in the package manifest that
+    /// is present in the layout
+    pub fn new(layout_dir: &Path, pkg_manifest: &PackageManifest) -> Result<Self, Error> {
+        let mut dirs = BTreeMap::<_, Vec<PackageDir>>::new();
+
+        for entry in std::fs::read_dir(layout_dir)
+            .with_context(|| format!("failed to read layout directory '{}'", layout_dir))?
+        {
+            let entry = entry.with_context(|| format!("failed to read entry in {}", layout_dir))?;
+            if !matches!(entry.file_type(), Ok(ft) if ft.is_dir()) {
+                continue;
+            }
+
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            if let Some((id, version, chip)) = parse_dir_name(&name) {
+                dirs.entry(id).or_default().push(PackageDir {
+                    path: layout_dir.join(&name),
+                    version,
+                    chip,
+                });
+            }
+        }
+
+        let mut payloads = BTreeMap::new();
+
+        for item in pkg_manifest.packages.values() {
+            let candidates = match dirs.get(&item.id.to_ascii_lowercase()) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+
+            let chip = item.chip.map(|chip| match chip {
+                Chip::X86 => "x86",
+                Chip::X64 => "x64",
+                Chip::Arm => "arm",
+                Chip::Arm64 => "arm64",
+                Chip::Neutral => "neutral",
+            });
+
+            for payload in &item.payloads {
+                // Payload filenames use Windows path separators, eg. `Installers\a.msi`
+                let file_name = payload.file_name.replace('\\', "/");
+
+                // The same package can be present multiple times, eg. for
+                // different languages, with files of the same name and size,
+                // so the checksum is checked when the payload is retrieved to
+                // find the one that actually matches
+                let found: Vec<_> = candidates
+                    .iter()
+                    .filter(|dir| {
+                        dir.version == item.version
+                            && (dir.chip.is_none() || dir.chip.as_deref() == chip)
+                    })
+                    .map(|dir| dir.path.join(&file_name))
+                    .filter(|path| {
+                        matches!(std::fs::metadata(path), Ok(md) if md.len() == payload.size)
+                    })
+                    .collect();
+
+                if !found.is_empty() {
+                    payloads
+                        .entry(payload.sha256.0)
+                        .or_insert_with(Vec::new)
+                        .extend(found);
+                }
+            }
+        }
+
+        tracing::debug!(
+            "found {} payloads in layout '{}'",
+            payloads.len(),
+            layout_dir
+        );
+
+        Ok(Self { payloads })
+    }
+}
+
+impl fetch::Fetcher for LayoutFetcher {
+    fn fetch(
+        &self,
+        url: &str,
+        offset: u64,
+        checksum: Option<&Sha256>,
+    ) -> Result<FetchResponse, FetchError> {
+        let (checksum, candidates) = checksum
+            .and_then(|checksum| Some((checksum, self.payloads.get(&checksum.0)?)))
+            .with_context(|| {
+                format!(
+                    "'{}' is not present in the layout, it might need to be recreated with the selected components",
+                    url
+                )
+            })
+            .map_err(FetchError::Fatal)?;
+
+        let path = candidates
+            .iter()
+            .find(
+                |path| match std::fs::File::open(path).and_then(Sha256::digest_reader) {
+                    Ok(chksum) if chksum == *checksum => true,
+                    Ok(chksum) => {
+                        tracing::debug!(
+                            "ignoring '{}' in layout, checksum {} != {}",
+                            path,
+                            chksum,
+                            checksum
+                        );
+                        false
+                    }
+                    Err(err) => {
+                        tracing::warn!(error = %err, "failed to read '{}' in layout", path);
+                        false
+                    }
+                },
+            )
+            .with_context(|| {
+                format!(
+                    "none of the files in the layout for '{}' match the checksum {}",
+                    url, checksum
+                )
+            })
+            .map_err(FetchError::Fatal)?;
+
+        fetch::open_file(path, offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fetch::Fetcher;
+    use std::io::Read;
+
+    #[test]
+    fn layout() {
+        assert_eq!(
+            parse_dir_name("Win10SDK_10.0.19041,version=10.0.19041.5,chip=X64"),
+            Some((
+                "win10sdk_10.0.19041".to_owned(),
+                "10.0.19041.5".to_owned(),
+                Some("x64".to_owned())
+            ))
+        );
+        assert_eq!(parse_dir_name("certificates"), None);
+
+        let td = tempfile::TempDir::new().unwrap();
+        let root = Path::from_path(td.path()).unwrap();
+
+        let msi = b"msi contents";
+        let cab = b"cab contents";
+
+        let pkg_dir = root.join("Win10SDK_10.0.19041,version=10.0.19041.5");
+        std::fs::create_dir_all(pkg_dir.join("Installers")).unwrap();
+        std::fs::write(pkg_dir.join("Installers/a.msi"), msi).unwrap();
+        std::fs::write(pkg_dir.join("Installers/b.cab"), cab).unwrap();
+
+        // Another language with a file of the same name and size
+        let lang_dir = root.join("Win10SDK_10.0.19041,version=10.0.19041.5,language=de-DE");
+        std::fs::create_dir_all(lang_dir.join("Installers")).unwrap();
+        std::fs::write(lang_dir.join("Installers/b.cab"), b"cab contentz").unwrap();
+
+        let payload = |file_name: &str, contents: &[u8]| {
+            serde_json::json!({
+                "fileName": file_name,
+                "sha256": Sha256::digest(contents).to_string(),
+                "size": contents.len(),
+                "url": format!("https://example.com/{}", file_name),
+            })
+        };
+
+        let catalog = serde_json::json!({
+            "manifestVersion": "1.1",
+            "packages": [{
+                "id": "Win10SDK_10.0.19041",
+                "version": "10.0.19041.5",
+                "type": "Msi",
+                "payloads": [
+                    payload("Installers\\a.msi", msi),
+                    payload("Installers\\b.cab", cab),
+                    payload("Installers\\missing.cab", b"missing"),
+                ],
+            }],
+        });
+        std::fs::write(
+            root.join("Catalog.json"),
+            serde_json::to_vec(&catalog).unwrap(),
+        )
+        .unwrap();
+
+        let pkg_manifest = read_catalog(root).unwrap();
+        let fetcher = LayoutFetcher::new(root, &pkg_manifest).unwrap();
+        assert_eq!(fetcher.payloads.len(), 2);
+        assert_eq!(fetcher.payloads[&Sha256::digest(cab).0].len(), 2);
+
+        let mut body = Vec::new();
+        fetcher
+            .fetch("https://example.com/b.cab", 0, Some(&Sha256::digest(cab)))
+            .unwrap()
+            .body
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(body, cab);
+
+        assert!(fetcher
+            .fetch(
+                "https://example.com/missing.cab",
+                0,
+                Some(&Sha256::digest(b"missing"))
+            )
+            .is_err());
+    }
+}
@@ -85,17 +85,34 @@ use anyhow::{Context as _, Error};
 pub use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
 use std::{collections::BTreeMap, fmt};
 
+mod authenticode;
+mod cabinet;
+pub mod cache;
+pub mod config;
 mod ctx;
+pub mod diff;
 mod download;
+pub mod fetch;
+mod http;
+pub mod layout;
+pub mod lock;
 pub mod manifest;
 mod splat;
 mod unpack;
 pub mod util;
 
-pub use ctx::Ctx;
+pub use authenticode::SignatureRoots;
+pub use ctx::{Ctx, Mirror};
+pub use download::resolve_cabs;
+pub use fetch::{FetchError, FetchResponse, Fetcher};
+pub use http::{HttpClient, HttpConfig};
 pub use splat::SplatConfig;
+pub use unpack::{IndexedFile, UnpackFilter, UnpackIndex};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Arch {
     X86 = 0x1,
     X86_64 = 0x2,
@@ -157,7 +174,10 @@ impl Arch {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Variant {
     Desktop = 0x1,
     OneCore = 0x2,
@@ -196,6 +216,14 @@ impl Variant {
         }
     }
 
+    /// Iterates over each variant, including spectre, that is set in the mask
+    pub fn iter_all(val: u32) -> impl Iterator<Item = Self> {
+        [Self::Desktop, Self::OneCore, Self::Store, Self::Spectre]
+            .iter()
+            .filter(move |var| **var as u32 & val != 0)
+            .copied()
+    }
+
     pub fn iter(val: u32) -> impl Iterator<Item = &'static str> {
         [Self::Desktop, Self::OneCore, Self::Store]
             .iter()
@@ -226,11 +254,12 @@ pub struct WorkItem {
     pub payload: std::sync::Arc<Payload>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Payload {
     /// The "suggested" filename for the payload when stored on disk
     pub filename: PathBuf,
     /// The sha-256 checksum of the payload
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
     pub sha256: util::Sha256,
     /// The url from which to acquire the payload
     pub url: String,
@@ -247,7 +276,8 @@ pub struct Payload {
     pub variant: Option<Variant>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PayloadKind {
     CrtHeaders,
     CrtLibs,
@@ -257,27 +287,224 @@ pub enum PayloadKind {
     Ucrt,
 }
 
+impl PayloadKind {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CrtHeaders => "crt_headers",
+            Self::CrtLibs => "crt_libs",
+            Self::SdkHeaders => "sdk_headers",
+            Self::SdkLibs => "sdk_libs",
+            Self::SdkStoreLibs => "sdk_store_libs",
+            Self::Ucrt => "ucrt",
+        }
+    }
+}
+
 /// Returns the list of packages that are actually needed for cross compilation
+///
+/// If `sdk_version` is specified, eg. `10.0.19041`, that exact Windows SDK
+/// version is selected, otherwise the latest version in the manifest is used.
+/// Likewise, `crt_version`, eg. `14.29.16.10`, selects an exact MSVC CRT
+/// toolset version rather than the latest one.
 pub fn prune_pkg_list(
     pkg_manifest: &manifest::PackageManifest,
     arches: u32,
     variants: u32,
+    sdk_version: Option<&str>,
+    crt_version: Option<&str>,
 ) -> Result<Vec<Payload>, Error> {
     // We only really need 2 core pieces from the manifest, the CRT (headers + libs)
     // and the Windows SDK
     let pkgs = &pkg_manifest.packages;
     let mut pruned = Vec::new();
 
-    get_crt(pkgs, arches, variants, &mut pruned)?;
-    get_sdk(pkgs, arches, &mut pruned)?;
+    get_crt(pkgs, arches, variants, crt_version, &mut pruned)?;
+    get_sdk(pkgs, arches, sdk_version, &mut pruned)?;
 
     Ok(pruned)
 }
 
+/// The CRT and SDK versions selected from a package manifest
+#[derive(Debug, PartialEq)]
+pub struct SelectedVersions {
+    pub crt: String,
+    pub sdk: String,
+}
+
+/// Returns the CRT and SDK versions that [`prune_pkg_list`] selects with the
+/// same version constraints
+pub fn select_versions(
+    pkg_manifest: &manifest::PackageManifest,
+    sdk_version: Option<&str>,
+    crt_version: Option<&str>,
+) -> Result<SelectedVersions, Error> {
+    let pkgs = &pkg_manifest.packages;
+
+    Ok(SelectedVersions {
+        crt: select_crt(pkgs, crt_version)?.to_owned(),
+        sdk: select_sdk(pkgs, sdk_version)?.0.to_owned(),
+    })
+}
+
+/// A CRT toolset version available in a package manifest
+#[derive(serde::Serialize, Debug)]
+pub struct CrtVersion {
+    /// The toolset version, eg. `14.29.16.10`
+    pub version: String,
+    /// The architectures that have CRT libraries in this version
+    pub arches: Vec<Arch>,
+    /// The variants that have CRT libraries in this version
+    pub variants: Vec<Variant>,
+}
+
+/// A Windows SDK version available in a package manifest
+#[derive(serde::Serialize, Debug)]
+pub struct SdkVersion {
+    /// The SDK version, eg. `10.0.19041`
+    pub version: String,
+    /// The package id of the SDK, eg. `Win10SDK_10.0.19041`
+    pub id: String,
+    /// The architectures that have SDK libraries in this version
+    pub arches: Vec<Arch>,
+}
+
+/// The CRT and SDK versions available in a package manifest
+#[derive(serde::Serialize, Debug)]
+pub struct Versions {
+    pub crt: Vec<CrtVersion>,
+    pub sdk: Vec<SdkVersion>,
+}
+
+/// Lists every CRT toolset and Windows SDK version in the package manifest,
+/// along with the architectures and variants each supports, any of which
+/// can be passed to [`prune_pkg_list`]
+pub fn list_versions(pkg_manifest: &manifest::PackageManifest) -> Versions {
+    let pkgs = &pkg_manifest.packages;
+
+    // Only toolsets with CRT packages are listed, as toolsets that are only
+    // referenced by a component can't be selected
+    let crt = crt_versions(pkgs)
+        .into_iter()
+        .map(|version| {
+            let mut arches = 0;
+            let mut variants = 0;
+
+            // The libs are in `Microsoft.VC.<version>.CRT.<arch>.<variant>[.spectre].base`
+            let prefix = format!("Microsoft.VC.{}.CRT.", version);
+            for lib in pkgs.keys().filter_map(|key| {
+                key.strip_prefix(&prefix)
+                    .and_then(|s| s.strip_suffix(".base"))
+            }) {
+                let (arch, rest) = match lib.split_once('.') {
+                    Some(split) => split,
+                    None => continue,
+                };
+
+                let arch =
+                    match Arch::iter(u32::MAX).find(|a| a.as_ms_str().eq_ignore_ascii_case(arch)) {
+                        Some(arch) => arch,
+                        None => continue,
+                    };
+
+                let (variant, spectre) = match rest.strip_suffix(".spectre") {
+                    Some(variant) => (variant, true),
+                    None => (rest, false),
+                };
+
+                let variant = match variant {
+                    "Desktop" => Variant::Desktop,
+                    "OneCore.Desktop" => Variant::OneCore,
+                    "Store" => Variant::Store,
+                    _ => continue,
+                };
+
+                arches |= arch as u32;
+                variants |= variant as u32;
+
+                if spectre {
+                    variants |= Variant::Spectre as u32;
+                }
+            }
+
+            CrtVersion {
+                version: version.to_owned(),
+                arches: Arch::iter(arches).collect(),
+                variants: Variant::iter_all(variants).collect(),
+            }
+        })
+        .collect();
+
+    let sdk = sdk_versions(pkgs)
+        .into_iter()
+        .map(|(version, _family, sdk)| SdkVersion {
+            version: version.to_owned(),
+            id: sdk.id.clone(),
+            arches: Arch::iter(u32::MAX)
+                .filter(|arch| {
+                    find_installer(
+                        sdk,
+                        &format!(
+                            "Windows SDK Desktop Libs {}-x86_en-us.msi",
+                            arch.as_ms_str()
+                        ),
+                    )
+                    .is_some()
+                })
+                .collect(),
+        })
+        .collect();
+
+    Versions { crt, sdk }
+}
+
+/// Gets the CRT toolset versions available in the manifest, from oldest to newest
+fn crt_versions(pkgs: &BTreeMap<String, manifest::ManifestItem>) -> Vec<&str> {
+    // Every toolset version has a `Microsoft.VC.<version>.CRT.Headers.base`
+    // package, so we use that to determine which versions are available
+    let mut crt_versions: Vec<_> = pkgs
+        .keys()
+        .filter_map(|key| {
+            key.strip_prefix("Microsoft.VC.")
+                .and_then(|s| s.strip_suffix(".CRT.Headers.base"))
+        })
+        .collect();
+
+    crt_versions.sort_by(|a, b| util::cmp_versions(a, b));
+    crt_versions
+}
+
+/// Selects the requested CRT toolset version, or the latest one if not specified
+fn select_crt<'p>(
+    pkgs: &'p BTreeMap<String, manifest::ManifestItem>,
+    crt_version: Option<&str>,
+) -> Result<&'p str, Error> {
+    let crt_versions = crt_versions(pkgs);
+
+    let crt_version = match crt_version {
+        Some(requested) => crt_versions
+            .iter()
+            .find(|version| **version == requested)
+            .with_context(|| {
+                format!(
+                    "unable to find CRT version '{}', available versions: {}",
+                    requested,
+                    crt_versions.join(", ")
+                )
+            })?,
+        None => crt_versions
+            .last()
+            .context("unable to find latest CRT version")?,
+    };
+
+    Ok(crt_version)
+}
+
 fn get_crt(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
     arches: u32,
     variants: u32,
+    crt_version: Option<&str>,
     pruned: &mut Vec<Payload>,
 ) -> Result<(), Error> {
     fn to_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Payload {
@@ -331,19 +558,7 @@ fn get_crt(
         }
     }
 
-    let build_tools = pkgs
-        .get("Microsoft.VisualStudio.Product.BuildTools")
-        .context("unable to find root BuildTools item")?;
-
-    let crt_version = build_tools
-        .dependencies
-        .keys()
-        .filter_map(|key| {
-            key.strip_prefix("Microsoft.VisualStudio.Component.VC.")
-                .and_then(|s| s.strip_suffix(".x86.x64"))
-        })
-        .last()
-        .context("unable to find latest CRT version")?;
+    let crt_version = select_crt(pkgs, crt_version)?;
 
     // The CRT headers are in the "base" package
     // `Microsoft.VC.<ridiculous_version_numbers>.CRT.Headers.base`
@@ -411,16 +626,110 @@ fn get_crt(
     Ok(())
 }
 
+/// The package families the Windows SDK is distributed under, Windows 10 SDKs
+/// are in `Win10SDK_10.*` packages, while Windows 11 SDKs, starting with
+/// `10.0.22000`, are in `Win11SDK_10.*` packages
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SdkFamily {
+    Win10,
+    Win11,
+}
+
+impl SdkFamily {
+    const ALL: [Self; 2] = [Self::Win10, Self::Win11];
+
+    #[inline]
+    fn id_prefix(self) -> &'static str {
+        match self {
+            Self::Win10 => "Win10SDK_",
+            Self::Win11 => "Win11SDK_",
+        }
+    }
+
+    /// Headers that are split out of the store headers MSI in newer SDKs
+    #[inline]
+    fn extra_store_headers(self) -> &'static [&'static str] {
+        match self {
+            Self::Win10 => &[],
+            Self::Win11 => &["Windows SDK for Windows Store Apps Headers OnecoreUap-x86_en-us.msi"],
+        }
+    }
+}
+
+/// Finds the payload for the specified installer. The installer payloads are
+/// usually, but not always, in an `Installers` subdirectory
+fn find_installer<'mi>(
+    sdk: &'mi manifest::ManifestItem,
+    installer: &str,
+) -> Option<&'mi manifest::Payload> {
+    sdk.payloads.iter().find(|payload| {
+        payload
+            .file_name
+            .strip_prefix("Installers\\")
+            .unwrap_or(&payload.file_name)
+            == installer
+    })
+}
+
+/// Gets the Windows SDKs available in the manifest, from oldest to newest
+fn sdk_versions(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+) -> Vec<(&str, SdkFamily, &manifest::ManifestItem)> {
+    // The ids are of the form `Win10SDK_<version>` or `Win11SDK_<version>`, the
+    // version needs to be compared numerically since eg. `10.0.9200` would
+    // otherwise be considered newer than `10.0.19041`
+    let mut sdks: Vec<_> = pkgs
+        .values()
+        .filter_map(|mi| {
+            SdkFamily::ALL.iter().find_map(|family| {
+                mi.id
+                    .strip_prefix(family.id_prefix())
+                    .filter(|version| version.starts_with("10."))
+                    .map(|version| (version, *family, mi))
+            })
+        })
+        .collect();
+
+    sdks.sort_by(|a, b| util::cmp_versions(a.0, b.0));
+    sdks
+}
+
+/// Selects the requested Windows SDK version, or the latest one if not specified
+fn select_sdk<'p>(
+    pkgs: &'p BTreeMap<String, manifest::ManifestItem>,
+    sdk_version: Option<&str>,
+) -> Result<(&'p str, SdkFamily, &'p manifest::ManifestItem), Error> {
+    let sdks = sdk_versions(pkgs);
+
+    let sdk = match sdk_version {
+        Some(requested) => sdks
+            .iter()
+            .find(|(version, ..)| *version == requested)
+            .with_context(|| {
+                format!(
+                    "unable to find Windows SDK version '{}', available versions: {}",
+                    requested,
+                    sdks.iter()
+                        .map(|(version, ..)| *version)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?,
+        None => sdks
+            .last()
+            .context("unable to find latest Windows SDK version")?,
+    };
+
+    Ok(*sdk)
+}
+
 fn get_sdk(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
     arches: u32,
+    sdk_version: Option<&str>,
     pruned: &mut Vec<Payload>,
 ) -> Result<(), Error> {
-    let sdk = pkgs
-        .values()
-        .filter(|mi| mi.id.starts_with("Win10SDK_10."))
-        .max()
-        .context("unable to find latest Win10SDK version")?;
+    let (_, family, sdk) = select_sdk(pkgs, sdk_version)?;
 
     // So. There are multiple SDK Desktop Headers, one per architecture. However,
     // all of the non-x86 ones include either 0 or few files, with x86 containing
@@ -428,14 +737,7 @@ fn get_sdk(
     // have all of them, as there are even more required headers in the completely
     // separate `Windows Store Apps Headers-x86` package as well. Incredibly annoying.
     {
-        let header_payload = sdk
-            .payloads
-            .iter()
-            .find(|payload| {
-                payload
-                    .file_name
-                    .ends_with("Windows SDK Desktop Headers x86-x86_en-us.msi")
-            })
+        let header_payload = find_installer(sdk, "Windows SDK Desktop Headers x86-x86_en-us.msi")
             .with_context(|| format!("unable to find headers for {}", sdk.id))?;
 
         pruned.push(Payload {
@@ -450,15 +752,10 @@ fn get_sdk(
             target_arch: None,
         });
 
-        let header_payload = sdk
-            .payloads
-            .iter()
-            .find(|payload| {
-                payload
-                    .file_name
-                    .ends_with("Windows SDK for Windows Store Apps Headers-x86_en-us.msi")
-            })
-            .with_context(|| format!("unable to find Windows SDK for Windows Store Apps Headers-x86_en-us.msi for {}", sdk.id))?;
+        const STORE_HEADERS: &str = "Windows SDK for Windows Store Apps Headers-x86_en-us.msi";
+
+        let header_payload = find_installer(sdk, STORE_HEADERS)
+            .with_context(|| format!("unable to find {} for {}", STORE_HEADERS, sdk.id))?;
 
         pruned.push(Payload {
             filename: format!("{}_store_headers.msi", sdk.id).into(),
@@ -471,22 +768,36 @@ fn get_sdk(
             target_arch: None,
         });
 
+        // Windows 11 SDKs split some of the store headers into additional
+        // installers, but not every version in the family has them
+        for (i, installer) in family.extra_store_headers().iter().enumerate() {
+            if let Some(header_payload) = find_installer(sdk, installer) {
+                pruned.push(Payload {
+                    filename: format!("{}_store_headers_{}.msi", sdk.id, i + 1).into(),
+                    sha256: header_payload.sha256.clone(),
+                    url: header_payload.url.clone(),
+                    size: header_payload.size,
+                    install_size: None,
+                    kind: PayloadKind::SdkHeaders,
+                    variant: Some(Variant::Store),
+                    target_arch: None,
+                });
+            }
+        }
+
         for arch in Arch::iter(arches) {
             if arch == Arch::X86 {
                 continue;
             }
 
-            let header_payload = sdk
-                .payloads
-                .iter()
-                .find(|payload| {
-                    payload
-                        .file_name
-                        .strip_prefix("Installers\\Windows SDK Desktop Headers ")
-                        .and_then(|fname| fname.strip_suffix("-x86_en-us.msi"))
-                        .map_or(false, |fname| fname == arch.as_ms_str())
-                })
-                .with_context(|| format!("unable to find {} headers for {}", arch, sdk.id))?;
+            let header_payload = find_installer(
+                sdk,
+                &format!(
+                    "Windows SDK Desktop Headers {}-x86_en-us.msi",
+                    arch.as_ms_str()
+                ),
+            )
+            .with_context(|| format!("unable to find {} headers for {}", arch, sdk.id))?;
 
             pruned.push(Payload {
                 filename: format!("{}_{}_headers.msi", sdk.id, arch.as_ms_str()).into(),
@@ -506,17 +817,14 @@ fn get_sdk(
     // kernel32 etc. :p
     {
         for arch in Arch::iter(arches) {
-            let lib = sdk
-                .payloads
-                .iter()
-                .find(|payload| {
-                    payload
-                        .file_name
-                        .strip_prefix("Installers\\Windows SDK Desktop Libs ")
-                        .and_then(|fname| fname.strip_suffix("-x86_en-us.msi"))
-                        .map_or(false, |arch_id| arch_id == arch.as_ms_str())
-                })
-                .with_context(|| format!("unable to find SDK libs for '{}'", arch))?;
+            let lib = find_installer(
+                sdk,
+                &format!(
+                    "Windows SDK Desktop Libs {}-x86_en-us.msi",
+                    arch.as_ms_str()
+                ),
+            )
+            .with_context(|| format!("unable to find SDK libs for '{}'", arch))?;
 
             pruned.push(Payload {
                 filename: format!("{}_libs_{}.msi", sdk.id, arch).into(),
@@ -530,20 +838,10 @@ fn get_sdk(
             });
         }
 
-        let lib_payload = sdk
-            .payloads
-            .iter()
-            .find(|payload| {
-                payload
-                    .file_name
-                    .ends_with("Windows SDK for Windows Store Apps Libs-x86_en-us.msi")
-            })
-            .with_context(|| {
-                format!(
-                    "unable to find Windows SDK for Windows Store Apps Libs-x86_en-us.msi for {}",
-                    sdk.id
-                )
-            })?;
+        const STORE_LIBS: &str = "Windows SDK for Windows Store Apps Libs-x86_en-us.msi";
+
+        let lib_payload = find_installer(sdk, STORE_LIBS)
+            .with_context(|| format!("unable to find {} for {}", STORE_LIBS, sdk.id))?;
 
         pruned.push(Payload {
             filename: format!("{}_store_libs.msi", sdk.id).into(),
@@ -585,3 +883,171 @@ fn get_sdk(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(id: &str, payloads: &[&str]) -> (String, manifest::ManifestItem) {
+        (
+            id.to_owned(),
+            manifest::ManifestItem {
+                id: id.to_owned(),
+                version: String::new(),
+                kind: manifest::ItemKind::Msi,
+                chip: None,
+                payloads: payloads
+                    .iter()
+                    .map(|file_name| manifest::Payload {
+                        file_name: (*file_name).to_owned(),
+                        sha256: util::Sha256([0; 32]),
+                        size: 0,
+                        url: String::new(),
+                    })
+                    .collect(),
+                dependencies: BTreeMap::new(),
+                install_sizes: None,
+            },
+        )
+    }
+
+    #[test]
+    fn versions() {
+        let pkg_manifest = manifest::PackageManifest {
+            packages: vec![
+                item("Microsoft.VC.14.9.1.CRT.Headers.base", &[]),
+                item("Microsoft.VC.14.29.16.10.CRT.Headers.base", &[]),
+                item("Microsoft.VC.14.29.16.10.CRT.x64.Desktop.base", &[]),
+                item(
+                    "Microsoft.VC.14.29.16.10.CRT.ARM64.Desktop.spectre.base",
+                    &[],
+                ),
+                item("Microsoft.VC.14.29.16.10.CRT.x86.OneCore.Desktop.base", &[]),
+                item(
+                    "Microsoft.VisualStudio.Component.VC.14.30.17.0.x86.x64",
+                    &[],
+                ),
+                item(
+                    "Win10SDK_10.0.19041",
+                    &["Installers\\Windows SDK Desktop Libs x64-x86_en-us.msi"],
+                ),
+                item("Win10SDK_10.0.9200", &[]),
+                item(
+                    "Win11SDK_10.0.22000",
+                    &[
+                        "Installers\\Windows SDK Desktop Libs x86-x86_en-us.msi",
+                        "Installers\\Windows SDK Desktop Libs arm64-x86_en-us.msi",
+                    ],
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            sha256: None,
+        };
+
+        let versions = list_versions(&pkg_manifest);
+
+        let crt: Vec<_> = versions.crt.iter().map(|c| c.version.as_str()).collect();
+        // 14.30.17.0 is only referenced by a component, so it can't be selected
+        assert_eq!(crt, ["14.9.1", "14.29.16.10"]);
+        assert_eq!(
+            versions.crt[1].arches,
+            [Arch::X86, Arch::X86_64, Arch::Aarch64]
+        );
+        assert_eq!(
+            versions.crt[1].variants,
+            [Variant::Desktop, Variant::OneCore, Variant::Spectre]
+        );
+        assert!(versions.crt[0].arches.is_empty());
+        assert!(select_versions(&pkg_manifest, None, Some("14.30.17.0")).is_err());
+
+        let sdk: Vec<_> = versions.sdk.iter().map(|s| s.version.as_str()).collect();
+        assert_eq!(sdk, ["10.0.9200", "10.0.19041", "10.0.22000"]);
+        assert_eq!(versions.sdk[1].arches, [Arch::X86_64]);
+        assert_eq!(versions.sdk[2].arches, [Arch::X86, Arch::Aarch64]);
+    }
+
+    #[test]
+    fn prunes_versions() {
+        const SDK_INSTALLERS: &[&str] = &[
+            "Installers\\Windows SDK Desktop Headers x86-x86_en-us.msi",
+            "Installers\\Windows SDK Desktop Headers x64-x86_en-us.msi",
+            "Installers\\Windows SDK for Windows Store Apps Headers-x86_en-us.msi",
+            "Installers\\Windows SDK Desktop Libs x64-x86_en-us.msi",
+            "Installers\\Windows SDK for Windows Store Apps Libs-x86_en-us.msi",
+        ];
+
+        let mut packages: BTreeMap<_, _> = ["14.28.29333", "14.29.16.10"]
+            .iter()
+            .flat_map(|version| {
+                ["Headers", "x64.Desktop", "x64.Store"]
+                    .iter()
+                    .map(move |pkg| {
+                        let id = format!("Microsoft.VC.{}.CRT.{}.base", version, pkg);
+                        let payload = format!("{}.vsix", id);
+                        item(&id, &[&payload])
+                    })
+            })
+            .collect();
+        packages.extend(vec![
+            item("Win10SDK_10.0.19041", SDK_INSTALLERS),
+            item("Win11SDK_10.0.22000", SDK_INSTALLERS),
+            item(
+                "Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi",
+                &["Universal CRT Headers Libraries and Sources-x86_en-us.msi"],
+            ),
+        ]);
+
+        let pkg_manifest = manifest::PackageManifest {
+            packages,
+            sha256: None,
+        };
+
+        let prune = |sdk_version, crt_version| {
+            let pruned = prune_pkg_list(
+                &pkg_manifest,
+                Arch::X86_64 as u32,
+                Variant::Desktop as u32,
+                sdk_version,
+                crt_version,
+            )?;
+
+            let crt = pruned
+                .iter()
+                .find(|payload| payload.kind == PayloadKind::CrtHeaders)
+                .unwrap();
+            let sdk = pruned
+                .iter()
+                .find(|payload| payload.kind == PayloadKind::SdkLibs)
+                .unwrap();
+
+            Ok::<_, Error>((crt.filename.to_string(), sdk.filename.to_string()))
+        };
+
+        assert_eq!(
+            prune(None, None).unwrap(),
+            (
+                "Microsoft.VC.14.29.16.10.CRT.Headers.base.vsix".to_owned(),
+                "Win11SDK_10.0.22000_libs_x86_64.msi".to_owned()
+            )
+        );
+        assert_eq!(
+            prune(Some("10.0.19041"), Some("14.28.29333")).unwrap(),
+            (
+                "Microsoft.VC.14.28.29333.CRT.Headers.base.vsix".to_owned(),
+                "Win10SDK_10.0.19041_libs_x86_64.msi".to_owned()
+            )
+        );
+
+        let err = prune(Some("10.0.17763"), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unable to find Windows SDK version '10.0.17763', available versions: 10.0.19041, 10.0.22000"
+        );
+        let err = prune(None, Some("14.30.30705")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unable to find CRT version '14.30.30705', available versions: 14.28.29333, 14.29.16.10"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{manifest, util::Sha256, Arch, Error, Path, PathBuf, Payload, Variant};
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The current version of the lockfile format
+const LOCK_VERSION: u32 = 1;
+
+/// A CAB file referenced by an MSI payload
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LockedCab {
+    pub filename: PathBuf,
+    pub url: String,
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub sha256: Sha256,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LockedPayload {
+    #[serde(flatten)]
+    pub payload: Payload,
+    /// The CAB files referenced by an MSI payload, empty for all other
+    /// payloads
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cabs: Vec<LockedCab>,
+}
+
+fn serialize_opt_sha256<S>(hash: &Option<Sha256>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match hash {
+        Some(hash) => crate::util::serialize_sha256(hash, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// A lockfile contains every payload selected from a package manifest, which
+/// allows subsequent runs to skip retrieving any manifests entirely
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Lockfile {
+    pub version: u32,
+    /// The checksum of the package manifest the payloads were resolved from
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_opt_sha256"
+    )]
+    pub package_manifest_sha256: Option<Sha256>,
+    /// The architectures the payloads were selected for
+    pub arches: Vec<Arch>,
+    /// The variants the payloads were selected for
+    pub variants: Vec<Variant>,
+    pub payloads: Vec<LockedPayload>,
+}
+
+impl Lockfile {
+    /// Creates a lockfile from the payloads selected via [`crate::prune_pkg_list`],
+    /// downloading each MSI to resolve the CABs it actually references via
+    /// [`crate::resolve_cabs`]
+    pub fn resolve(
+        ctx: &crate::Ctx,
+        pkg_manifest: &manifest::PackageManifest,
+        payloads: Vec<Payload>,
+        arches: u32,
+        variants: u32,
+        progress: indicatif::ProgressBar,
+    ) -> Result<Self, Error> {
+        let payloads = crate::resolve_cabs(ctx, pkg_manifest, payloads, progress)?;
+
+        Ok(Self::new(pkg_manifest, payloads, arches, variants))
+    }
+
+    /// Creates a lockfile from payloads whose CABs have already been resolved
+    fn new(
+        pkg_manifest: &manifest::PackageManifest,
+        payloads: Vec<LockedPayload>,
+        arches: u32,
+        variants: u32,
+    ) -> Self {
+        Self {
+            version: LOCK_VERSION,
+            package_manifest_sha256: pkg_manifest.sha256.clone(),
+            arches: Arch::iter(arches).collect(),
+            variants: Variant::iter_all(variants).collect(),
+            payloads,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents =
+            std::fs::read(path).with_context(|| format!("failed to read lockfile '{}'", path))?;
+        let lock: Self = serde_json::from_slice(&contents)
+            .with_context(|| format!("failed to deserialize lockfile '{}'", path))?;
+
+        anyhow::ensure!(
+            lock.version == LOCK_VERSION,
+            "lockfile '{}' has version {}, but only version {} is supported",
+            path,
+            lock.version,
+            LOCK_VERSION
+        );
+
+        Ok(lock)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let contents = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("failed to write lockfile '{}'", path))
+    }
+
+    #[inline]
+    pub fn arches(&self) -> u32 {
+        self.arches.iter().fold(0, |acc, arch| acc | *arch as u32)
+    }
+
+    #[inline]
+    pub fn variants(&self) -> u32 {
+        self.variants.iter().fold(0, |acc, var| acc | *var as u32)
+    }
+
+    /// Splits the lockfile into the list of payloads, and a package manifest
+    /// containing only the locked payloads and CABs, which is all that is
+    /// needed to download and unpack them
+    pub fn into_parts(self) -> (manifest::PackageManifest, Vec<Payload>) {
+        let mut packages = BTreeMap::new();
+        let mut payloads = Vec::with_capacity(self.payloads.len());
+
+        for locked in self.payloads {
+            let id = locked.payload.filename.to_string();
+
+            let item = manifest::ManifestItem {
+                id: id.clone(),
+                version: String::new(),
+                kind: match locked.payload.filename.extension() {
+                    Some("msi") => manifest::ItemKind::Msi,
+                    _ => manifest::ItemKind::Vsix,
+                },
+                chip: None,
+                payloads: std::iter::once(manifest::Payload {
+                    file_name: id.clone(),
+                    sha256: locked.payload.sha256.clone(),
+                    size: locked.payload.size,
+                    url: locked.payload.url.clone(),
+                })
+                .chain(locked.cabs.into_iter().map(|cab| manifest::Payload {
+                    file_name: cab.filename.into_string(),
+                    sha256: cab.sha256,
+                    size: cab.size,
+                    url: cab.url,
+                }))
+                .collect(),
+                dependencies: BTreeMap::new(),
+                install_sizes: None,
+            };
+
+            packages.insert(id, item);
+            payloads.push(locked.payload);
+        }
+
+        (
+            manifest::PackageManifest {
+                packages,
+                sha256: self.package_manifest_sha256,
+            },
+            payloads,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let payload = |file_name: &str, fill: u8| manifest::Payload {
+            file_name: file_name.to_owned(),
+            sha256: Sha256([fill; 32]),
+            size: fill as u64,
+            url: format!("https://example.com/{}", file_name),
+        };
+
+        let mut packages = BTreeMap::new();
+        packages.insert(
+            "sdk".to_owned(),
+            manifest::ManifestItem {
+                id: "sdk".to_owned(),
+                version: "1".to_owned(),
+                kind: manifest::ItemKind::Msi,
+                chip: None,
+                payloads: vec![
+                    payload("Installers\\headers.msi", 1),
+                    payload("Installers\\one.cab", 2),
+                    payload("Installers\\two.cab", 3),
+                ],
+                dependencies: BTreeMap::new(),
+                install_sizes: None,
+            },
+        );
+
+        let pkg_manifest = manifest::PackageManifest {
+            packages,
+            sha256: Some(Sha256([9; 32])),
+        };
+
+        let selected = vec![Payload {
+            filename: "sdk_headers.msi".into(),
+            sha256: Sha256([1; 32]),
+            url: "https://example.com/Installers\\headers.msi".to_owned(),
+            size: 1,
+            install_size: None,
+            kind: crate::PayloadKind::SdkHeaders,
+            target_arch: None,
+            variant: None,
+        }];
+
+        // The MSI only references one of the CABs in its package
+        let cab = &pkg_manifest.packages["sdk"].payloads[1];
+        let locked = vec![LockedPayload {
+            payload: selected.into_iter().next().unwrap(),
+            cabs: vec![LockedCab {
+                filename: "one.cab".into(),
+                url: cab.url.clone(),
+                sha256: cab.sha256.clone(),
+                size: cab.size,
+            }],
+        }];
+
+        let lock = Lockfile::new(
+            &pkg_manifest,
+            locked,
+            Arch::X86_64 as u32,
+            Variant::Desktop as u32 | Variant::Spectre as u32,
+        );
+
+        let serialized = serde_json::to_vec(&lock).unwrap();
+        let lock: Lockfile = serde_json::from_slice(&serialized).unwrap();
+
+        assert_eq!(lock.package_manifest_sha256, Some(Sha256([9; 32])));
+        assert_eq!(lock.arches(), Arch::X86_64 as u32);
+        assert_eq!(
+            lock.variants(),
+            Variant::Desktop as u32 | Variant::Spectre as u32
+        );
+
+        let (pkg_manifest, payloads) = lock.into_parts();
+        assert_eq!(payloads.len(), 1);
+
+        let cabs = crate::download::find_cabs(&pkg_manifest.packages, &payloads[0]).unwrap();
+        let cabs: Vec<_> = cabs.iter().map(|cab| cab.filename.as_str()).collect();
+        assert_eq!(cabs, ["one.cab"]);
+    }
+}
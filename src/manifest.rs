@@ -2,7 +2,7 @@ use anyhow::{ensure, Context as _};
 use serde::Deserialize;
 use std::{cmp, collections::BTreeMap};
 
-use crate::Ctx;
+use crate::{util::Sha256, Ctx};
 
 #[derive(Deserialize, Debug)]
 pub struct Payload {
@@ -107,6 +107,32 @@ pub struct Manifest {
     channel_items: Vec<ManifestItem>,
 }
 
+impl Manifest {
+    /// Gets the payload for the package manifest, and the filename it is
+    /// cached as
+    pub(crate) fn package_manifest(&self) -> Result<(&Payload, String), anyhow::Error> {
+        let pkg_manifest = self
+            .channel_items
+            .iter()
+            .find(|ci| ci.kind == ItemKind::Manifest && !ci.payloads.is_empty())
+            .context("Unable to locate package manifest")?;
+
+        // This always just a single payload, but ensure it stays that way in the future
+        ensure!(
+            pkg_manifest.payloads.len() == 1,
+            "VS package manifest should have exactly 1 payload"
+        );
+
+        // While the payload includes a sha256 checksum for the payload it is actually
+        // never correct (even though it is part of the url!) so we can't use it,
+        // the user can however pin the checksum of the actual contents
+        let payload = &pkg_manifest.payloads[0];
+        let filename = format!("pkg_manifest_{}.vsman", payload.sha256);
+
+        Ok((payload, filename))
+    }
+}
+
 /// Retrieves the top-level manifest which contains license links as well as the
 /// link to the actual package manifest which describes all of the contents
 pub fn get_manifest(
@@ -127,53 +153,54 @@ pub fn get_manifest(
     Ok(manifest)
 }
 
-/// Retrieves the package manifest specified in the input manifest
+/// Retrieves the package manifest specified in the input manifest. If a
+/// checksum is provided the package manifest must match it exactly.
 pub fn get_package_manifest(
     ctx: &Ctx,
     manifest: &Manifest,
+    checksum: Option<Sha256>,
     progress: indicatif::ProgressBar,
 ) -> Result<PackageManifest, anyhow::Error> {
-    let pkg_manifest = manifest
-        .channel_items
-        .iter()
-        .find(|ci| ci.kind == ItemKind::Manifest && !ci.payloads.is_empty())
-        .context("Unable to locate package manifest")?;
-
-    // This always just a single payload, but ensure it stays that way in the future
-    ensure!(
-        pkg_manifest.payloads.len() == 1,
-        "VS package manifest should have exactly 1 payload"
-    );
-
-    // While the payload includes a sha256 checksum for the payload it is actually
-    // never correct (even though it is part of the url!) so we have to just download
-    // it without checking, which is terrible but...¯\_(ツ)_/¯
-    let payload = &pkg_manifest.payloads[0];
-
-    let manifest_bytes = ctx.get_and_validate(
-        payload.url.clone(),
-        &format!("pkg_manifest_{}.vsman", payload.sha256),
-        None,
-        progress,
-    )?;
-
-    #[derive(Deserialize)]
-    struct PkgManifest {
-        packages: Vec<ManifestItem>,
-    }
-
-    let manifest: PkgManifest =
-        serde_json::from_slice(&manifest_bytes).context("unable to parse manifest")?;
+    let (payload, filename) = manifest.package_manifest()?;
 
-    let mut packages = BTreeMap::new();
+    let manifest_bytes =
+        ctx.get_and_validate(payload.url.clone(), &filename, checksum, progress)?;
 
-    for pkg in manifest.packages {
-        packages.insert(pkg.id.clone(), pkg);
+    let pkg_manifest = PackageManifest::from_slice(&manifest_bytes)?;
+    if let Some(sha256) = &pkg_manifest.sha256 {
+        tracing::info!("package manifest sha256: {}", sha256);
     }
 
-    Ok(PackageManifest { packages })
+    Ok(pkg_manifest)
 }
 
 pub struct PackageManifest {
     pub packages: BTreeMap<String, ManifestItem>,
+    /// The actual checksum of the package manifest contents, `None` if the
+    /// package manifest was not retrieved, eg. when using a lockfile
+    pub sha256: Option<Sha256>,
+}
+
+impl PackageManifest {
+    /// Parses the contents of a package manifest
+    pub fn from_slice(manifest_bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        #[derive(Deserialize)]
+        struct PkgManifest {
+            packages: Vec<ManifestItem>,
+        }
+
+        let manifest: PkgManifest =
+            serde_json::from_slice(manifest_bytes).context("unable to parse manifest")?;
+
+        let mut packages = BTreeMap::new();
+
+        for pkg in manifest.packages {
+            packages.insert(pkg.id.clone(), pkg);
+        }
+
+        Ok(Self {
+            packages,
+            sha256: Some(Sha256::digest(manifest_bytes)),
+        })
+    }
 }
@@ -96,6 +96,7 @@ pub(crate) fn splat(
     roots: &SplatRoots,
     item: &crate::WorkItem,
     tree: crate::unpack::FileTree,
+    unpack_filter: &crate::UnpackFilter,
     arches: u32,
     variants: u32,
 ) -> Result<Option<SdkHeaders>, Error> {
@@ -122,7 +123,7 @@ pub(crate) fn splat(
     let variant = item.payload.variant;
     let kind = item.payload.kind;
 
-    let get_tree = |src_path: &Path| -> Result<&crate::unpack::FileTree, Error> {
+    let get_tree = |src_path: &Path| -> Result<Option<&crate::unpack::FileTree>, Error> {
         let src_path = src_path
             .strip_prefix(&roots.src)
             .context("incorrect src root")?;
@@ -130,8 +131,19 @@ pub(crate) fn splat(
             .strip_prefix(&item.payload.filename)
             .context("incorrect src subdir")?;
 
-        tree.subtree(src_path)
-            .with_context(|| format!("missing expected subtree '{}'", src_path))
+        match tree.subtree(src_path) {
+            Some(tree) => Ok(Some(tree)),
+            // Entire subtrees can be removed by the unpack filter, eg. every
+            // `lib` directory with `--unpack-include 'include/**'`
+            None if !unpack_filter.is_empty() => {
+                tracing::debug!(
+                    "skipping subtree '{}' removed by the unpack filter",
+                    src_path
+                );
+                Ok(None)
+            }
+            None => anyhow::bail!("missing expected subtree '{}'", src_path),
+        }
     };
 
     let mappings = match item.payload.kind {
@@ -139,13 +151,15 @@ pub(crate) fn splat(
             src.push("include");
             let tree = get_tree(&src)?;
 
-            vec![Mapping {
+            tree.map(|tree| Mapping {
                 src,
                 target: roots.crt.join("include"),
                 tree,
                 kind,
                 variant,
-            }]
+            })
+            .into_iter()
+            .collect()
         }
         PayloadKind::CrtLibs => {
             src.push("lib");
@@ -192,25 +206,29 @@ pub(crate) fn splat(
 
             let tree = get_tree(&src)?;
 
-            vec![Mapping {
+            tree.map(|tree| Mapping {
                 src,
                 target,
                 tree,
                 kind,
                 variant,
-            }]
+            })
+            .into_iter()
+            .collect()
         }
         PayloadKind::SdkHeaders => {
             src.push("include");
             let tree = get_tree(&src)?;
 
-            vec![Mapping {
+            tree.map(|tree| Mapping {
                 src,
                 target: roots.sdk.join("include"),
                 tree,
                 kind,
                 variant,
-            }]
+            })
+            .into_iter()
+            .collect()
         }
         PayloadKind::SdkLibs => {
             src.push("lib/um");
@@ -231,24 +249,29 @@ pub(crate) fn splat(
 
             let tree = get_tree(&src)?;
 
-            vec![Mapping {
+            tree.map(|tree| Mapping {
                 src,
                 target,
                 tree,
                 kind,
                 variant,
-            }]
+            })
+            .into_iter()
+            .collect()
         }
         PayloadKind::SdkStoreLibs => {
             src.push("lib/um");
             let target = roots.sdk.join("lib/um");
 
             Arch::iter(arches)
-                .map(|arch| -> Result<Mapping<'_>, Error> {
+                .filter_map(|arch| {
                     let src = src.join(arch.as_ms_str());
-                    let tree = get_tree(&src)?;
+                    let tree = match get_tree(&src) {
+                        Ok(tree) => tree?,
+                        Err(err) => return Some(Err(err)),
+                    };
 
-                    Ok(Mapping {
+                    Some(Ok(Mapping {
                         src,
                         target: target.join(if config.preserve_ms_arch_notation {
                             arch.as_ms_str()
@@ -258,27 +281,32 @@ pub(crate) fn splat(
                         tree,
                         kind,
                         variant,
-                    })
+                    }))
                 })
-                .collect::<Result<Vec<_>, _>>()?
+                .collect::<Result<Vec<_>, Error>>()?
         }
         PayloadKind::Ucrt => {
             let inc_src = src.join("include/ucrt");
-            let tree = get_tree(&inc_src)?;
+            let mut mappings = Vec::new();
 
-            let mut mappings = vec![Mapping {
-                src: inc_src,
-                target: roots.sdk.join("include/ucrt"),
-                tree,
-                kind,
-                variant,
-            }];
+            if let Some(tree) = get_tree(&inc_src)? {
+                mappings.push(Mapping {
+                    src: inc_src,
+                    target: roots.sdk.join("include/ucrt"),
+                    tree,
+                    kind,
+                    variant,
+                });
+            }
 
             src.push("lib/ucrt");
             let target = roots.sdk.join("lib/ucrt");
             for arch in Arch::iter(arches) {
                 let src = src.join(arch.as_ms_str());
-                let tree = get_tree(&src)?;
+                let tree = match get_tree(&src)? {
+                    Some(tree) => tree,
+                    None => continue,
+                };
 
                 mappings.push(Mapping {
                     src,
@@ -297,6 +325,13 @@ pub(crate) fn splat(
         }
     };
 
+    // Everything was removed by the unpack filter, so there is nothing to
+    // splat, or to symlink to
+    if mappings.is_empty() {
+        item.progress.finish_with_message("📦 nothing to splat");
+        return Ok(None);
+    }
+
     let include_debug_libs = config.include_debug_libs;
     let include_debug_symbols = config.include_debug_symbols;
 
@@ -714,3 +749,96 @@ fn calc_lower_hash(path: &str) -> u64 {
 
     hasher.finish()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn skips_filtered_subtrees() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = Path::from_path(td.path()).unwrap();
+
+        let roots = SplatRoots {
+            crt: root.join("splat/crt"),
+            sdk: root.join("splat/sdk"),
+            src: root.join("unpack"),
+        };
+
+        let config = SplatConfig {
+            include_debug_libs: false,
+            include_debug_symbols: false,
+            enable_symlinks: false,
+            preserve_ms_arch_notation: false,
+            output: root.join("splat"),
+            copy: true,
+        };
+
+        let item = |kind| crate::WorkItem {
+            payload: std::sync::Arc::new(crate::Payload {
+                filename: "x.msi".into(),
+                sha256: crate::util::Sha256([0; 32]),
+                url: String::new(),
+                size: 0,
+                install_size: None,
+                kind,
+                target_arch: Some(Arch::X86_64),
+                variant: None,
+            }),
+            progress: indicatif::ProgressBar::hidden(),
+        };
+
+        // Only the headers were unpacked, eg. with `--unpack-include 'include/**'`
+        let header = roots.src.join("x.msi/include/ucrt/stdio.h");
+        std::fs::create_dir_all(header.parent().unwrap()).unwrap();
+        std::fs::write(&header, "#pragma once").unwrap();
+
+        let tree = || {
+            let mut tree = crate::unpack::FileTree::new();
+            tree.push(Path::new("include/ucrt/stdio.h"), 12);
+            tree
+        };
+
+        let no_filter = crate::UnpackFilter::default();
+        let filter = crate::UnpackFilter::new(vec!["include/**".to_owned()], Vec::new()).unwrap();
+        let arches = Arch::X86_64 as u32;
+        let variants = Variant::Desktop as u32;
+
+        // A missing subtree is still an error if nothing was filtered
+        assert!(splat(
+            &config,
+            &roots,
+            &item(PayloadKind::SdkLibs),
+            tree(),
+            &no_filter,
+            arches,
+            variants
+        )
+        .is_err());
+
+        splat(
+            &config,
+            &roots,
+            &item(PayloadKind::SdkLibs),
+            tree(),
+            &filter,
+            arches,
+            variants,
+        )
+        .unwrap();
+        assert!(!roots.sdk.join("lib").exists());
+
+        splat(
+            &config,
+            &roots,
+            &item(PayloadKind::Ucrt),
+            tree(),
+            &filter,
+            arches,
+            variants,
+        )
+        .unwrap();
+        assert!(roots.sdk.join("include/ucrt/stdio.h").exists());
+        assert!(!roots.sdk.join("lib").exists());
+    }
+}
@@ -8,6 +8,192 @@ pub(crate) struct UnpackMeta {
     pub(crate) compressed: u64,
     pub(crate) decompressed: u64,
     pub(crate) num_files: u32,
+    /// The number of files that were verified against their hash in the MSI's
+    /// `MsiFileHash` table, which only contains unversioned files
+    #[serde(default)]
+    pub(crate) verified_files: u32,
+    /// The [`UnpackFilter`] patterns the payload was unpacked with, the payload
+    /// is unpacked again if they change
+    #[serde(default)]
+    pub(crate) include: Vec<String>,
+    #[serde(default)]
+    pub(crate) exclude: Vec<String>,
+}
+
+/// The name of the index written alongside the unpacked contents
+pub(crate) const INDEX_FILE: &str = ".index.json";
+/// The current version of the index format, an index with a different version
+/// is ignored and the unpack directory is scanned instead
+const INDEX_VERSION: u32 = 1;
+
+/// A file unpacked from a payload
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct IndexedFile {
+    /// The path of the file relative to the unpack directory, always separated
+    /// by `/` regardless of the platform
+    pub path: String,
+    pub size: u64,
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub sha256: crate::util::Sha256,
+}
+
+/// The index of every file unpacked from a payload, written to `.index.json`
+/// in its unpack directory so that the directory doesn't need to be scanned
+/// again when the payload is already unpacked
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UnpackIndex {
+    pub version: u32,
+    /// The unpacked files, sorted by their path
+    pub files: Vec<IndexedFile>,
+}
+
+impl UnpackIndex {
+    fn new(mut files: Vec<IndexedFile>) -> Self {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self {
+            version: INDEX_VERSION,
+            files,
+        }
+    }
+
+    /// Reads the index from an unpack directory, eg. `.xwin-cache/unpack/<payload>`
+    pub fn read(unpack_dir: &Path) -> Result<Self, Error> {
+        let index_path = unpack_dir.join(INDEX_FILE);
+        let index =
+            std::fs::read(&index_path).with_context(|| format!("unable to read {}", index_path))?;
+        let index: Self = serde_json::from_slice(&index)
+            .with_context(|| format!("unable to parse {}", index_path))?;
+
+        anyhow::ensure!(
+            index.version == INDEX_VERSION,
+            "{} has version {}, but only version {} is supported",
+            index_path,
+            index.version,
+            INDEX_VERSION
+        );
+
+        Ok(index)
+    }
+
+    fn tree(&self) -> FileTree {
+        let mut tree = FileTree::new();
+        for file in &self.files {
+            tree.push(Path::new(&file.path), file.size);
+        }
+        tree
+    }
+}
+
+/// Normalizes a path relative to the unpack directory for the index
+fn index_path(path: &Path) -> String {
+    path.iter().collect::<Vec<_>>().join("/")
+}
+
+/// User supplied glob patterns that select which files are unpacked, eg.
+/// `include/cppwinrt`. Patterns are matched case insensitively against the
+/// `/` separated path of each file relative to the unpack directory, as well
+/// as every parent directory of the file, so a pattern that matches a
+/// directory applies to everything in it.
+#[derive(Default)]
+pub struct UnpackFilter {
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    include_set: globset::GlobSet,
+    exclude_set: globset::GlobSet,
+}
+
+impl UnpackFilter {
+    /// Creates a filter that unpacks files that match any of the `include`
+    /// patterns, or every file if there are none, except for those that match
+    /// any of the `exclude` patterns
+    pub fn new(mut include: Vec<String>, mut exclude: Vec<String>) -> Result<Self, Error> {
+        fn build(patterns: &[String]) -> Result<globset::GlobSet, Error> {
+            let mut set = globset::GlobSetBuilder::new();
+            for pattern in patterns {
+                set.add(
+                    globset::GlobBuilder::new(pattern)
+                        .case_insensitive(true)
+                        .literal_separator(true)
+                        .build()
+                        .with_context(|| format!("invalid glob '{}'", pattern))?,
+                );
+            }
+            Ok(set.build()?)
+        }
+
+        // The patterns are stored in the unpack metadata, so normalize them to
+        // avoid unpacking again just because they were specified differently
+        include.sort();
+        include.dedup();
+        exclude.sort();
+        exclude.dedup();
+
+        Ok(Self {
+            include_set: build(&include)?,
+            exclude_set: build(&exclude)?,
+            include,
+            exclude,
+        })
+    }
+
+    /// Checks if there are no patterns, ie. every file is unpacked
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Checks if the file, whose path is relative to the unpack directory,
+    /// should be unpacked
+    pub fn is_match(&self, path: &str) -> bool {
+        let matches = |set: &globset::GlobSet| {
+            std::iter::successors(Some(path), |path| path.rfind('/').map(|i| &path[..i]))
+                .any(|path| set.is_match(path))
+        };
+
+        (self.include.is_empty() || matches(&self.include_set)) && !matches(&self.exclude_set)
+    }
+}
+
+/// Writes an unpacked file, updating the progress and hashing its contents as
+/// it goes
+struct Wrapper<'pb> {
+    pb: &'pb indicatif::ProgressBar,
+    uf: std::fs::File,
+    md5: Option<md5::Md5>,
+    sha256: sha2::Sha256,
+}
+
+impl<'pb> Wrapper<'pb> {
+    fn new(pb: &'pb indicatif::ProgressBar, uf: std::fs::File, md5: bool) -> Self {
+        use sha2::Digest;
+
+        Self {
+            pb,
+            uf,
+            md5: md5.then(md5::Md5::new),
+            sha256: sha2::Sha256::new(),
+        }
+    }
+}
+
+impl<'pb> std::io::Write for Wrapper<'pb> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+
+        let written = self.uf.write(buf)?;
+        self.pb.inc(written as u64);
+
+        if let Some(md5) = &mut self.md5 {
+            md5.update(&buf[..written]);
+        }
+        self.sha256.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.uf.flush()
+    }
 }
 
 #[derive(Debug)]
@@ -17,14 +203,14 @@ pub(crate) struct FileTree {
 }
 
 impl FileTree {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             files: Vec::new(),
             dirs: Vec::new(),
         }
     }
 
-    fn push(&mut self, path: &Path, size: u64) {
+    pub(crate) fn push(&mut self, path: &Path, size: u64) {
         let fname = path.file_name().unwrap();
         let mut tree = self;
 
@@ -85,7 +271,9 @@ fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
                 )
             })?;
 
-            if src_name == ".unpack" {
+            // Skip the metadata written alongside the unpacked contents,
+            // including the `tree.txt` written by previous versions
+            if src_name == ".unpack" || src_name == INDEX_FILE || src_name == "tree.txt" {
                 continue;
             }
 
@@ -118,6 +306,233 @@ fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
     Ok(root_tree)
 }
 
+/// Reads the MD5 hashes of unversioned files, eg. headers and import libraries,
+/// from the `MsiFileHash` table, keyed by their file id
+fn read_file_hashes<R: std::io::Read + std::io::Seek>(
+    msi: &mut msi::Package<R>,
+) -> Result<std::collections::BTreeMap<String, [u8; 16]>, Error> {
+    if !msi.has_table("MsiFileHash") {
+        return Ok(std::collections::BTreeMap::new());
+    }
+
+    msi.select_rows(msi::Select::table("MsiFileHash"))?
+        .map(|row| -> Result<_, Error> {
+            // Columns:
+            // 0 - File_ (file id)
+            // 1 - Options
+            // 2..=5 - HashPart1..HashPart4, the 128-bit hash as 4 little
+            // endian 32-bit integers
+            anyhow::ensure!(row.len() >= 6, "invalid row in 'MsiFileHash'");
+
+            let id = row[0].as_str().context("File_ is not a string")?.to_owned();
+
+            let mut hash = [0u8; 16];
+            for (i, part) in hash.chunks_mut(4).enumerate() {
+                let value = row[2 + i].as_int().context("hash part is not an integer")?;
+                part.copy_from_slice(&value.to_le_bytes());
+            }
+
+            Ok((id, hash))
+        })
+        .collect()
+}
+
+/// A file in an MSI's `File` table
+pub(crate) struct MsiFile {
+    /// The id of the file, which is also its name in the CAB
+    pub(crate) id: String,
+    /// The path of the file relative to the unpack directory
+    pub(crate) name: PathBuf,
+    pub(crate) size: u64,
+}
+
+/// Reads the files that are unpacked from an MSI, ie. every file in its
+/// `File` table that isn't in one of the directories we don't care about, or
+/// excluded by the filter
+pub(crate) fn read_msi_files<R: std::io::Read + std::io::Seek>(
+    msi: &mut msi::Package<R>,
+    pkg: &Path,
+    filter: &UnpackFilter,
+) -> Result<Vec<MsiFile>, Error> {
+    // Open source ftw https://gitlab.gnome.org/GNOME/msitools/-/blob/master/tools/msiextract.vala
+
+    // For some reason many filenames in the table(s) have a weird
+    // checksum(?) filename with an extension separated from the
+    // _actual_ filename with a `|` so we need to detect that and
+    // strip off just the real name we want
+    #[inline]
+    fn fix_name(name: &msi::Value) -> Result<&str, Error> {
+        let name = name.as_str().context("filename is not a string")?;
+
+        Ok(match name.find('|') {
+            Some(ind) => &name[ind + 1..],
+            None => name,
+        })
+    }
+
+    let components = {
+        #[derive(Debug)]
+        struct Dir {
+            id: String,
+            parent: Option<String>,
+            path: PathBuf,
+        }
+
+        // Collect the directories that can be referenced by a component
+        // that are reference by files. Ugh.
+        let mut directories: Vec<_> = msi
+            .select_rows(msi::Select::table("Directory"))
+            .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
+            .map(|row| -> Result<_, _> {
+                // Columns:
+                // 0 - Directory (name)
+                // 1 - Directory_Parent (name of parent)
+                // 2 - DefaultDir (location of directory on disk)
+                // ...
+                anyhow::ensure!(row.len() >= 3, "invalid row in 'Directory'");
+
+                Ok(Dir {
+                    id: row[0]
+                        .as_str()
+                        .context("directory name is not a string")?
+                        .to_owned(),
+                    // This can be `null`
+                    parent: row[1].as_str().map(String::from),
+                    path: fix_name(&row[2])?.into(),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("unable to read directories for {}", pkg))?;
+
+        directories.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let components: std::collections::BTreeMap<_, _> = msi
+            .select_rows(msi::Select::table("Component"))
+            .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
+            .map(|row| -> Result<_, _> {
+                // Columns:
+                // 0 - Component (name, really, id)
+                // 1 - ComponentId
+                // 2 - Directory_ (directory id)
+                anyhow::ensure!(row.len() >= 3, "invalid row in 'Component'");
+
+                // The recursion depth for directory lookup is quite shallow
+                // typically, the full path to a file would be something like
+                // `Program Files/Windows Kits/10/Lib/10.0.19041.0/um/x64`
+                // but this a terrible path, so we massage it to instead be
+                // `lib/um/x64`
+                fn build_dir(dirs: &[Dir], id: &str, dir: &mut PathBuf) {
+                    #[allow(clippy::single_match_else)]
+                    let cur_dir = match dirs.binary_search_by(|d| d.id.as_str().cmp(id)) {
+                        Ok(i) => &dirs[i],
+                        Err(_) => {
+                            tracing::warn!("unable to find directory {}", id);
+                            return;
+                        }
+                    };
+
+                    match cur_dir.path.file_name() {
+                        Some("Lib") => {
+                            dir.push("lib");
+                        }
+                        Some("Include") => {
+                            dir.push("include");
+                        }
+                        other => {
+                            if let Some(parent) = &cur_dir.parent {
+                                build_dir(dirs, parent, dir);
+                            }
+
+                            if let Some(other) = other {
+                                // Ignore the SDK version directory between
+                                // Lib/Include and the actual subdirs we care about
+                                if !other.starts_with(|c: char| c.is_digit(10)) {
+                                    dir.push(other);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let component_id = row[0]
+                    .as_str()
+                    .context("component id is not a string")?
+                    .to_owned();
+
+                let mut dir = PathBuf::new();
+                build_dir(
+                    &directories,
+                    row[2]
+                        .as_str()
+                        .context("component directory is not a string")?,
+                    &mut dir,
+                );
+
+                Ok((component_id, dir))
+            })
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("unable to read components for {}", pkg))?;
+
+        components
+    };
+
+    msi.select_rows(msi::Select::table("File"))
+        .with_context(|| format!("MSI {} has no 'File' table", pkg))?
+        .filter_map(|row| -> Option<Result<_, Error>> {
+            // Columns:
+            // 0 - File Id (lookup in CAB)
+            // 1 - Component_ (target directory)
+            // 2 - FileName
+            // 3 - FileSize
+            // 4 - Version
+            // 5 - Language
+            // 6 - Attributes
+            // 7 - Sequence (determines which CAB file)
+            if row.len() < 8 {
+                return Some(Err(anyhow::anyhow!("invalid row in 'File'")));
+            }
+
+            let (dir, fname, id, size) = match || -> Result<_, Error> {
+                let fname = fix_name(&row[2])?;
+                let dir = components
+                    .get(row[1].as_str().context("component id was not a string")?)
+                    .with_context(|| format!("file {} referenced an unknown component", row[2]))?;
+
+                let size = row[3].as_int().context("size is not an integer")? as u64;
+                let id = row[0].as_str().context("File (id) is not a string")?;
+
+                Ok((dir, fname, id, size))
+            }() {
+                Ok(items) => items,
+                Err(e) => return Err(e).transpose(),
+            };
+
+            // Directories are relative to the unpack directory
+            if let Some(camino::Utf8Component::Normal(first)) = dir.components().next() {
+                match first {
+                    "Catalogs" | "bin" | "Source" | "SourceDir" => {
+                        //tracing::debug!("ignoring {}/{}", dir, fname);
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+
+            let name = dir.join(fname);
+            if !filter.is_match(&index_path(&name)) {
+                return None;
+            }
+
+            Some(Ok(MsiFile {
+                id: id.to_owned(),
+                name,
+                size,
+            }))
+        })
+        .collect::<Result<_, Error>>()
+        .with_context(|| format!("unable to read 'File' metadata for {}", pkg))
+}
+
 pub(crate) fn unpack(
     ctx: std::sync::Arc<Ctx>,
     item: &crate::WorkItem,
@@ -128,18 +543,27 @@ pub(crate) fn unpack(
 
     let output_dir = match ctx.prep_unpack(&item.payload)? {
         crate::ctx::Unpack::Present { output_dir, .. } => {
-            return read_unpack_dir(output_dir);
+            return match UnpackIndex::read(&output_dir) {
+                Ok(index) => Ok(index.tree()),
+                Err(err) => {
+                    // Directories unpacked by previous versions don't have an index
+                    tracing::debug!("{:#}, scanning {} instead", err, output_dir);
+                    read_unpack_dir(output_dir)
+                }
+            };
         }
         crate::ctx::Unpack::Needed(od) => od,
     };
 
     let pkg = &item.payload.filename;
 
-    let (tree, compressed) = match contents {
+    let (files, compressed, verified_files) = match contents {
         PayloadContents::Vsix(vsix) => {
-            let mut tree = FileTree::new();
+            let mut files = Vec::new();
 
-            let mut zip = zip::ZipArchive::new(std::io::Cursor::new(vsix))
+            let mut zip = std::fs::File::open(&vsix)
+                .map_err(Error::from)
+                .and_then(|file| Ok(zip::ZipArchive::new(std::io::BufReader::new(file))?))
                 .with_context(|| format!("invalid zip {}", pkg))?;
 
             // VSIX files are just a "specially" formatted zip file, all
@@ -155,7 +579,17 @@ pub(crate) fn unpack(
                 if fname.starts_with("Contents/")
                     && (fname.contains("lib") || fname.contains("include"))
                 {
-                    to_extract.push(findex);
+                    // Strip everything before the lib or include directory
+                    let rel_path: PathBuf = Path::new(fname)
+                        .components()
+                        .skip_while(|comp| comp.as_str() != "lib" && comp.as_str() != "include")
+                        .collect();
+
+                    if !ctx.unpack_filter.is_match(&index_path(&rel_path)) {
+                        continue;
+                    }
+
+                    to_extract.push((findex, rel_path));
                     total_uncompressed += file.size();
                 }
             }
@@ -164,17 +598,9 @@ pub(crate) fn unpack(
 
             let mut total_compressed = 0;
 
-            for findex in to_extract {
+            for (findex, rel_path) in to_extract {
                 let mut file = zip.by_index(findex).unwrap();
-                let zip_path = Path::new(file.name());
-                let mut fs_path = output_dir.clone();
-
-                for comp in zip_path
-                    .components()
-                    .skip_while(|comp| comp.as_str() != "lib" && comp.as_str() != "include")
-                {
-                    fs_path.push(comp);
-                }
+                let fs_path = output_dir.join(&rel_path);
 
                 if let Some(parent) = fs_path.parent() {
                     if !parent.exists() {
@@ -183,7 +609,7 @@ pub(crate) fn unpack(
                     }
                 }
 
-                let mut dest = std::fs::File::create(&fs_path).with_context(|| {
+                let dest = std::fs::File::create(&fs_path).with_context(|| {
                     format!(
                         "unable to create {} to decompress {} from {}",
                         fs_path,
@@ -192,7 +618,8 @@ pub(crate) fn unpack(
                     )
                 })?;
 
-                let decompressed = std::io::copy(&mut file, &mut dest).with_context(|| {
+                let mut wrapper = Wrapper::new(&item.progress, dest, false);
+                let decompressed = std::io::copy(&mut file, &mut wrapper).with_context(|| {
                     format!(
                         "unable to decompress {} from {} to {}",
                         file.name(),
@@ -201,381 +628,268 @@ pub(crate) fn unpack(
                     )
                 })?;
 
-                item.progress.inc(decompressed);
-
-                let tree_path = fs_path.strip_prefix(&output_dir).unwrap();
-                tree.push(tree_path, decompressed);
+                files.push(IndexedFile {
+                    path: index_path(&rel_path),
+                    size: decompressed,
+                    sha256: crate::util::Sha256(sha2::Digest::finalize(wrapper.sha256).into()),
+                });
 
                 total_compressed += file.compressed_size();
             }
 
-            (tree, total_compressed)
+            (files, total_compressed, 0)
         }
         PayloadContents::Msi { msi, cabs } => {
-            let mut msi = msi::Package::open(std::io::Cursor::new(msi))
+            let mut msi = std::fs::File::open(&msi)
+                .map_err(Error::from)
+                .and_then(|file| Ok(msi::Package::open(std::io::BufReader::new(file))?))
                 .with_context(|| format!("unable to read MSI from {}", pkg))?;
 
-            // Open source ftw https://gitlab.gnome.org/GNOME/msitools/-/blob/master/tools/msiextract.vala
-
-            // For some reason many filenames in the table(s) have a weird
-            // checksum(?) filename with an extension separated from the
-            // _actual_ filename with a `|` so we need to detect that and
-            // strip off just the real name we want
-            #[inline]
-            fn fix_name(name: &msi::Value) -> Result<&str, Error> {
-                let name = name.as_str().context("filename is not a string")?;
-
-                Ok(match name.find('|') {
-                    Some(ind) => &name[ind + 1..],
-                    None => name,
-                })
-            }
-
-            let components = {
-                #[derive(Debug)]
-                struct Dir {
-                    id: String,
-                    parent: Option<String>,
-                    path: PathBuf,
-                }
-
-                // Collect the directories that can be referenced by a component
-                // that are reference by files. Ugh.
-                let mut directories: Vec<_> = msi
-                    .select_rows(msi::Select::table("Directory"))
-                    .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
-                    .map(|row| -> Result<_, _> {
-                        // Columns:
-                        // 0 - Directory (name)
-                        // 1 - Directory_Parent (name of parent)
-                        // 2 - DefaultDir (location of directory on disk)
-                        // ...
-                        anyhow::ensure!(row.len() >= 3, "invalid row in 'Directory'");
-
-                        Ok(Dir {
-                            id: row[0]
-                                .as_str()
-                                .context("directory name is not a string")?
-                                .to_owned(),
-                            // This can be `null`
-                            parent: row[1].as_str().map(String::from),
-                            path: fix_name(&row[2])?.into(),
-                        })
-                    })
-                    .collect::<Result<_, _>>()
-                    .with_context(|| format!("unable to read directories for {}", pkg))?;
-
-                directories.sort_by(|a, b| a.id.cmp(&b.id));
-
-                let components: std::collections::BTreeMap<_, _> = msi
-                    .select_rows(msi::Select::table("Component"))
-                    .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
-                    .map(|row| -> Result<_, _> {
-                        // Columns:
-                        // 0 - Component (name, really, id)
-                        // 1 - ComponentId
-                        // 2 - Directory_ (directory id)
-                        anyhow::ensure!(row.len() >= 3, "invalid row in 'Component'");
-
-                        // The recursion depth for directory lookup is quite shallow
-                        // typically, the full path to a file would be something like
-                        // `Program Files/Windows Kits/10/Lib/10.0.19041.0/um/x64`
-                        // but this a terrible path, so we massage it to instead be
-                        // `lib/um/x64`
-                        fn build_dir(dirs: &[Dir], id: &str, dir: &mut PathBuf) {
-                            #[allow(clippy::single_match_else)]
-                            let cur_dir = match dirs.binary_search_by(|d| d.id.as_str().cmp(id)) {
-                                Ok(i) => &dirs[i],
-                                Err(_) => {
-                                    tracing::warn!("unable to find directory {}", id);
-                                    return;
-                                }
-                            };
-
-                            match cur_dir.path.file_name() {
-                                Some("Lib") => {
-                                    dir.push("lib");
-                                }
-                                Some("Include") => {
-                                    dir.push("include");
-                                }
-                                other => {
-                                    if let Some(parent) = &cur_dir.parent {
-                                        build_dir(dirs, parent, dir);
-                                    }
-
-                                    if let Some(other) = other {
-                                        // Ignore the SDK version directory between
-                                        // Lib/Include and the actual subdirs we care about
-                                        if !other.starts_with(|c: char| c.is_digit(10)) {
-                                            dir.push(other);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        let component_id = row[0]
-                            .as_str()
-                            .context("component id is not a string")?
-                            .to_owned();
-
-                        let mut dir = PathBuf::new();
-                        build_dir(
-                            &directories,
-                            row[2]
-                                .as_str()
-                                .context("component directory is not a string")?,
-                            &mut dir,
-                        );
-
-                        Ok((component_id, dir))
-                    })
-                    .collect::<Result<_, _>>()
-                    .with_context(|| format!("unable to read components for {}", pkg))?;
-
-                components
-            };
-
             struct Cab {
-                /// The max sequence number, each `File` in an MSI has a
-                /// sequence number that maps to exactly one CAB file
-                sequence: u32,
                 path: PathBuf,
-                cab: bytes::Bytes,
+                /// The location of the CAB in the download cache
+                cab: PathBuf,
+                cabinet: crate::cabinet::Cabinet,
             }
 
-            let cabs = {
-                let mut cab_contents = Vec::with_capacity(cabs.len());
-
-                for cab in cabs {
+            let cabs = cabs
+                .into_iter()
+                .map(|cab| -> Result<_, Error> {
                     // Validate the cab file
-                    cab::Cabinet::new(std::io::Cursor::new(cab.content.clone()))
+                    let cabinet = std::fs::File::open(&cab.content)
+                        .map_err(Error::from)
+                        .and_then(|file| {
+                            crate::cabinet::Cabinet::read(&mut std::io::BufReader::new(file))
+                        })
                         .with_context(|| format!("CAB {} is invalid", cab.path))?;
 
-                    cab_contents.push(Cab {
-                        sequence: cab.sequence,
+                    Ok(Cab {
                         path: cab.path,
                         cab: cab.content,
-                    });
-                }
-
-                // They are usually always sorted correctly, but you never know
-                cab_contents.sort_by(|a, b| a.sequence.cmp(&b.sequence));
-                cab_contents
-            };
+                        cabinet,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
 
             anyhow::ensure!(!cabs.is_empty(), "no cab files were referenced by the MSI");
 
+            let hashes = read_file_hashes(&mut msi)
+                .with_context(|| format!("unable to read 'MsiFileHash' for {}", pkg))?;
+
             struct CabFile {
-                id: String,
                 name: PathBuf,
                 size: u64,
-                sequence: u32,
+                /// The expected MD5 hash, if the file is unversioned
+                md5: Option<[u8; 16]>,
             }
 
-            let (files, uncompressed) = {
+            // The files to extract, keyed by their id, which is also their
+            // name in the CAB
+            let (mut files, uncompressed) = {
                 let mut uncompressed = 0u64;
-                let mut files: Vec<_> = msi
-                    .select_rows(msi::Select::table("File"))
-                    .with_context(|| format!("MSI {} has no 'File' table", pkg))?
-                    .filter_map(|row| -> Option<Result<_, Error>> {
-                        // Columns:
-                        // 0 - File Id (lookup in CAB)
-                        // 1 - Component_ (target directory)
-                        // 2 - FileName
-                        // 3 - FileSize
-                        // 4 - Version
-                        // 5 - Language
-                        // 6 - Attributes
-                        // 7 - Sequence (determines which CAB file)
-                        if row.len() < 8 {
-                            return Some(Err(anyhow::anyhow!("invalid row in 'File'")));
-                        }
-
-                        let (dir, fname, id, seq, size) = match || -> Result<_, Error> {
-                            let fname = fix_name(&row[2])?;
-                            let dir = components
-                                .get(row[1].as_str().context("component id was not a string")?)
-                                .with_context(|| {
-                                    format!("file {} referenced an unknown component", row[2])
-                                })?;
-
-                            let size = row[3].as_int().context("size is not an integer")? as u64;
-                            let id = row[0].as_str().context("File (id) is not a string")?;
-                            let seq = row[7].as_int().context("sequence is not an integer")? as u32;
-
-                            Ok((dir, fname, id, seq, size))
-                        }() {
-                            Ok(items) => items,
-                            Err(e) => return Err(e).transpose(),
-                        };
-
-                        if let Some(camino::Utf8Component::Normal(first)) = dir
-                            .strip_prefix(&output_dir)
-                            .ok()
-                            .and_then(|rel| rel.components().next())
-                        {
-                            match first {
-                                "Catalogs" | "bin" | "Source" | "SourceDir" => {
-                                    //tracing::debug!("ignoring {}/{}", dir, fname);
-                                    return None;
-                                }
-                                _ => {}
-                            }
-                        }
-
-                        uncompressed += size;
-
-                        let cf = CabFile {
-                            id: id.to_owned(),
-                            name: dir.join(fname),
-                            sequence: seq,
-                            size,
-                        };
-
-                        Some(Ok(cf))
-                    })
-                    .collect::<Result<Vec<_>, Error>>()
-                    .with_context(|| format!("unable to read 'File' metadata for {}", pkg))?;
+                let files: std::collections::BTreeMap<_, _> =
+                    read_msi_files(&mut msi, pkg, &ctx.unpack_filter)?
+                        .into_iter()
+                        .map(|file| {
+                            uncompressed += file.size;
+
+                            let cf = CabFile {
+                                name: file.name,
+                                size: file.size,
+                                md5: hashes.get(&file.id).copied(),
+                            };
 
-                files.sort_by(|a, b| a.sequence.cmp(&b.sequence));
+                            (file.id, cf)
+                        })
+                        .collect();
 
                 (files, uncompressed)
             };
 
             item.progress.set_length(uncompressed);
 
-            // Some MSIs have a lot of cabs and take an _extremely_ long time to
-            // decompress, so we just split the files into roughly equal sized
-            // chunks and decompress in parallel to reduce wall time
-            let mut chunks = Vec::new();
-
-            struct Chunk {
-                cab: bytes::Bytes,
-                cab_index: usize,
-                files: Vec<CabFile>,
-                chunk_size: u64,
+            // The data in a CAB folder is compressed as a single stream, so
+            // each folder is decompressed in a single pass, extracting the
+            // files we want in the order they appear in the stream. Some MSIs
+            // have a lot of cabs and take an _extremely_ long time to
+            // decompress, so the folders are decompressed in parallel to
+            // reduce wall time.
+            struct FolderJob<'cab> {
+                cab: &'cab Cab,
+                index: usize,
+                files: Vec<(&'cab crate::cabinet::FolderFile, CabFile)>,
             }
 
-            chunks.push(Chunk {
-                cab: cabs[0].cab.clone(),
-                cab_index: 0,
-                files: Vec::new(),
-                chunk_size: 0,
-            });
-
-            let mut cur_chunk = 0;
-            let mut cur_cab = 0;
-            const CHUNK_SIZE: u64 = 1024 * 1024;
-
-            for file in files {
-                let chunk = &mut chunks[cur_chunk];
-
-                if chunk.chunk_size + file.size < CHUNK_SIZE
-                    && file.sequence <= cabs[cur_cab].sequence
-                {
-                    chunk.chunk_size += file.size;
-                    chunk.files.push(file);
-                } else {
-                    let cab = if file.sequence <= cabs[cur_cab].sequence {
-                        chunk.cab.clone()
-                    } else {
-                        match cabs[cur_cab + 1..]
-                            .iter()
-                            .position(|cab| file.sequence <= cab.sequence)
-                        {
-                            Some(i) => cur_cab += i + 1,
-                            None => anyhow::bail!(
-                                "unable to find cab file containing {} {}",
-                                file.name,
-                                file.sequence
-                            ),
-                        }
-
-                        cabs[cur_cab].cab.clone()
-                    };
-
-                    cur_chunk += 1;
-                    chunks.push(Chunk {
-                        cab,
-                        cab_index: cur_cab,
-                        chunk_size: file.size,
-                        files: vec![file],
-                    });
+            let mut jobs = Vec::new();
+
+            for cab in &cabs {
+                for (index, folder) in cab.cabinet.folders.iter().enumerate() {
+                    let folder_files: Vec<_> = folder
+                        .files
+                        .iter()
+                        .filter_map(|ff| files.remove(&ff.name).map(|file| (ff, file)))
+                        .collect();
+
+                    if !folder_files.is_empty() {
+                        jobs.push(FolderJob {
+                            cab,
+                            index,
+                            files: folder_files,
+                        });
+                    }
                 }
             }
 
-            let mut results = Vec::new();
+            if let Some((id, file)) = files.iter().next() {
+                anyhow::bail!(
+                    "unable to find '{}' ({}) in any of the CABs referenced by {}",
+                    file.name,
+                    id,
+                    pkg
+                );
+            }
 
             use rayon::prelude::*;
 
-            let tree = parking_lot::Mutex::new(FileTree::new());
+            let indexed = parking_lot::Mutex::new(Vec::new());
+            let verified_files = std::sync::atomic::AtomicU32::new(0);
+
+            jobs.into_par_iter()
+                .map(|job| -> Result<(), Error> {
+                    use std::io::Read;
+
+                    let FolderJob { cab, index, files } = job;
+                    let cab_path = &cab.path;
+
+                    // Each folder opens its own handle to the CAB so that they
+                    // can be decompressed independently of each other
+                    let open_folder = || -> Result<_, Error> {
+                        let file = std::fs::File::open(&cab.cab)
+                            .with_context(|| format!("unable to open {}", cab.cab))?;
+                        cab.cabinet
+                            .folder_reader(std::io::BufReader::new(file), index)
+                            .with_context(|| {
+                                format!("unable to read folder {} of {}", index, cab_path)
+                            })
+                    };
+
+                    let mut folder = open_folder()?;
+                    let mut pos = 0;
 
-            chunks
-                .into_par_iter()
-                .map(|chunk| -> Result<(), Error> {
-                    let mut cab = cab::Cabinet::new(std::io::Cursor::new(chunk.cab)).unwrap();
+                    for (ff, file) in files {
+                        // Files are sorted by their offset so this should never
+                        // happen, but if files overlap, ie. share the same data,
+                        // the stream just needs to be started over
+                        if ff.offset < pos {
+                            folder = open_folder()?;
+                            pos = 0;
+                        }
 
-                    let cab_path = &cabs[chunk.cab_index].path;
+                        // Skip the data of any files in between that we don't want
+                        let skip = ff.offset - pos;
+                        let skipped =
+                            std::io::copy(&mut (&mut folder).take(skip), &mut std::io::sink())
+                                .with_context(|| {
+                                    format!("unable to read folder {} of {}", index, cab_path)
+                                })?;
+                        anyhow::ensure!(
+                            skipped == skip,
+                            "folder {} of {} ended before '{}'",
+                            index,
+                            cab_path,
+                            file.name
+                        );
 
-                    for file in chunk.files {
-                        let mut cab_file = match cab.read_file(file.id.as_str()) {
-                            Ok(cf) => cf,
-                            Err(e) => Err(e).with_context(|| {
-                                format!("unable to read '{}' from {}", file.name, cab_path,)
-                            })?,
-                        };
+                        pos = ff.offset + ff.size;
+                        let mut cab_file = (&mut folder).take(ff.size);
 
                         let unpack_path = output_dir.join(&file.name);
 
                         if let Some(parent) = unpack_path.parent() {
                             if !parent.exists() {
-                                std::fs::create_dir_all(parent)?;
+                                std::fs::create_dir_all(parent).with_context(|| {
+                                    format!("unable to create unpack dir '{}'", parent)
+                                })?;
                             }
                         }
 
-                        let unpacked_file = std::fs::File::create(&unpack_path)?;
+                        let unpacked_file =
+                            std::fs::File::create(&unpack_path).with_context(|| {
+                                format!(
+                                    "unable to create {} to decompress {} from {}",
+                                    unpack_path, file.name, cab_path
+                                )
+                            })?;
+
+                        let mut wrapper =
+                            Wrapper::new(&item.progress, unpacked_file, file.md5.is_some());
+
+                        let size =
+                            std::io::copy(&mut cab_file, &mut wrapper).with_context(|| {
+                                format!(
+                                    "unable to decompress '{}' from {} to {}",
+                                    file.name, cab_path, unpack_path
+                                )
+                            })?;
+
+                        // A truncated or corrupt CAB can still decompress
+                        // "successfully", so ensure we got the entire file
+                        anyhow::ensure!(
+                            size == file.size,
+                            "'{}' from {} is {} bytes, but the MSI expects {} bytes",
+                            file.name,
+                            cab_path,
+                            size,
+                            file.size
+                        );
 
-                        struct Wrapper<'pb> {
-                            pb: &'pb indicatif::ProgressBar,
-                            uf: std::fs::File,
-                        }
+                        if let (Some(expected), Some(md5)) = (file.md5, wrapper.md5) {
+                            let actual: [u8; 16] = md5::Digest::finalize(md5).into();
 
-                        impl<'pb> std::io::Write for Wrapper<'pb> {
-                            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-                                self.pb.inc(buf.len() as u64);
-                                self.uf.write(buf)
-                            }
+                            anyhow::ensure!(
+                                actual == expected,
+                                "'{}' from {} does not match its hash in the MSI",
+                                file.name,
+                                cab_path
+                            );
 
-                            fn flush(&mut self) -> std::io::Result<()> {
-                                self.uf.flush()
-                            }
+                            verified_files.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
                         }
 
-                        let size = std::io::copy(
-                            &mut cab_file,
-                            &mut Wrapper {
-                                pb: &item.progress,
-                                uf: unpacked_file,
-                            },
-                        )?;
-
-                        tree.lock().push(&file.name, size);
+                        indexed.lock().push(IndexedFile {
+                            path: index_path(&file.name),
+                            size,
+                            sha256: crate::util::Sha256(
+                                sha2::Digest::finalize(wrapper.sha256).into(),
+                            ),
+                        });
                     }
 
                     Ok(())
                 })
-                .collect_into_vec(&mut results);
+                .collect::<Result<(), Error>>()
+                .with_context(|| format!("unable to unpack {}", pkg))?;
+
+            tracing::debug!(
+                "verified {} files from {} against 'MsiFileHash'",
+                verified_files.load(std::sync::atomic::Ordering::Relaxed),
+                pkg
+            );
 
-            (tree.into_inner(), uncompressed)
+            (
+                indexed.into_inner(),
+                uncompressed,
+                verified_files.into_inner(),
+            )
         }
     };
 
-    let tree_path = format!("{}/tree.txt", output_dir);
+    let index = UnpackIndex::new(files);
+    let tree = index.tree();
 
-    std::fs::write(&tree_path, format!("{:#?}", tree).as_bytes())
-        .with_context(|| format!("failed to write {}", tree_path))?;
+    let index_file = output_dir.join(INDEX_FILE);
+    std::fs::write(&index_file, serde_json::to_vec(&index)?)
+        .with_context(|| format!("failed to write {}", index_file))?;
 
     item.progress.finish_with_message("unpacked");
 
@@ -588,8 +902,138 @@ pub(crate) fn unpack(
             compressed,
             decompressed,
             num_files,
+            verified_files,
+            include: ctx.unpack_filter.include.clone(),
+            exclude: ctx.unpack_filter.exclude.clone(),
         },
     )?;
 
     Ok(tree)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_hashes() {
+        use md5::Digest;
+        use msi::{Column, Insert, Value};
+        use std::convert::TryInto;
+
+        let mut msi = msi::Package::create(
+            msi::PackageType::Installer,
+            std::io::Cursor::new(Vec::new()),
+        )
+        .unwrap();
+        assert!(read_file_hashes(&mut msi).unwrap().is_empty());
+
+        let expected: [u8; 16] = md5::Md5::digest(b"#pragma once").into();
+
+        let mut row = vec![Value::from("windows.h"), Value::Int(0)];
+        row.extend(
+            expected
+                .chunks(4)
+                .map(|part| Value::Int(i32::from_le_bytes(part.try_into().unwrap()))),
+        );
+
+        msi.create_table(
+            "MsiFileHash",
+            vec![
+                Column::build("File_").primary_key().id_string(72),
+                Column::build("Options").int16(),
+                Column::build("HashPart1").int32(),
+                Column::build("HashPart2").int32(),
+                Column::build("HashPart3").int32(),
+                Column::build("HashPart4").int32(),
+            ],
+        )
+        .unwrap();
+        msi.insert_rows(Insert::into("MsiFileHash").row(row))
+            .unwrap();
+
+        let hashes = read_file_hashes(&mut msi).unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes["windows.h"], expected);
+    }
+
+    #[test]
+    fn index() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = Path::from_path(td.path()).unwrap();
+
+        let file = |path: &str, size: u64| IndexedFile {
+            path: path.to_owned(),
+            size,
+            sha256: crate::util::Sha256([size as u8; 32]),
+        };
+
+        let index = UnpackIndex::new(vec![
+            file("lib/x64/ucrt.lib", 300),
+            file("include/ucrt/stdio.h", 20),
+            file("include/ucrt/sys/stat.h", 1),
+        ]);
+        std::fs::write(root.join(INDEX_FILE), serde_json::to_vec(&index).unwrap()).unwrap();
+
+        let index = UnpackIndex::read(root).unwrap();
+        assert_eq!(
+            index
+                .files
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "include/ucrt/stdio.h",
+                "include/ucrt/sys/stat.h",
+                "lib/x64/ucrt.lib"
+            ]
+        );
+
+        let tree = index.tree();
+        assert_eq!(tree.stats(), (3, 321));
+        assert_eq!(
+            tree.subtree(Path::new("include/ucrt")).unwrap().stats(),
+            (2, 21)
+        );
+
+        let mut index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(root.join(INDEX_FILE)).unwrap()).unwrap();
+        index["version"] = serde_json::json!(INDEX_VERSION + 1);
+        std::fs::write(root.join(INDEX_FILE), serde_json::to_vec(&index).unwrap()).unwrap();
+        assert!(UnpackIndex::read(root).is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let all = UnpackFilter::default();
+        assert!(all.is_match("include/cppwinrt/winrt/base.h"));
+
+        let filter = UnpackFilter::new(
+            vec!["include".to_owned(), "lib/**/*.lib".to_owned()],
+            vec!["include/cppwinrt".to_owned(), "include/*/msclr".to_owned()],
+        )
+        .unwrap();
+
+        assert!(filter.is_match("include/um/Windows.h"));
+        assert!(filter.is_match("Include/ucrt/stdio.h"));
+        assert!(filter.is_match("lib/um/x64/kernel32.lib"));
+        assert!(!filter.is_match("lib/um/x64/kernel32.pdb"));
+        assert!(!filter.is_match("include/cppwinrt/winrt/base.h"));
+        assert!(!filter.is_match("include/crt/msclr/marshal.h"));
+        assert!(!filter.is_match("bin/x64/rc.exe"));
+
+        assert!(UnpackFilter::new(vec!["include/[".to_owned()], Vec::new()).is_err());
+
+        let reordered = UnpackFilter::new(
+            vec![
+                "lib/**/*.lib".to_owned(),
+                "include".to_owned(),
+                "include".to_owned(),
+            ],
+            vec!["include/*/msclr".to_owned(), "include/cppwinrt".to_owned()],
+        )
+        .unwrap();
+        assert_eq!(reordered.include, filter.include);
+        assert_eq!(reordered.exclude, filter.exclude);
+    }
+}
@@ -122,6 +122,43 @@ impl Sha256 {
 
         Self(digest.into())
     }
+
+    /// Computes the digest of everything read from the reader, without
+    /// needing to have the entire contents in memory
+    pub fn digest_reader(mut reader: impl std::io::Read) -> std::io::Result<Self> {
+        use sha2::Digest;
+
+        let mut hasher = sha2::Sha256::new();
+        std::io::copy(&mut reader, &mut hasher)?;
+        let digest = hasher.finalize();
+
+        Ok(Self(digest.into()))
+    }
+}
+
+/// Compares two dotted version strings, eg `10.0.19041`, component by
+/// component, numerically where possible, rather than lexicographically.
+pub fn cmp_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+
+    loop {
+        match (a.next(), b.next()) {
+            (Some(a), Some(b)) => {
+                let ord = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => a.cmp(b),
+                };
+
+                if ord != std::cmp::Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (None, None) => return std::cmp::Ordering::Equal,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -137,4 +174,14 @@ mod test {
 
         assert_eq!(digest, hex.parse::<Sha256>().unwrap());
     }
+
+    #[test]
+    fn versions() {
+        use std::cmp::Ordering;
+
+        assert_eq!(cmp_versions("10.0.9200", "10.0.19041"), Ordering::Less);
+        assert_eq!(cmp_versions("14.29.16.10", "14.29.16.10"), Ordering::Equal);
+        assert_eq!(cmp_versions("14.30.17.0", "14.29.16.10"), Ordering::Greater);
+        assert_eq!(cmp_versions("10.0.19041", "10.0.19041.1"), Ordering::Less);
+    }
 }
@@ -12,12 +12,14 @@ fn verify_compiles() {
 
     let manifest = xwin::manifest::get_manifest(&ctx, "16", "release", hidden.clone()).unwrap();
     let pkg_manifest =
-        xwin::manifest::get_package_manifest(&ctx, &manifest, hidden.clone()).unwrap();
+        xwin::manifest::get_package_manifest(&ctx, &manifest, None, hidden.clone()).unwrap();
 
     let pruned = xwin::prune_pkg_list(
         &pkg_manifest,
         xwin::Arch::X86_64 as u32,
         xwin::Variant::Desktop as u32,
+        None,
+        None,
     )
     .unwrap();
 
@@ -16,12 +16,14 @@ fn verify_deterministic() {
     let manifest_contents = std::fs::read_to_string("tests/deterministic_manifest.json").unwrap();
     let manifest: xwin::manifest::Manifest = serde_json::from_str(&manifest_contents).unwrap();
     let pkg_manifest =
-        xwin::manifest::get_package_manifest(&ctx, &manifest, hidden.clone()).unwrap();
+        xwin::manifest::get_package_manifest(&ctx, &manifest, None, hidden.clone()).unwrap();
 
     let pruned = xwin::prune_pkg_list(
         &pkg_manifest,
         xwin::Arch::X86_64 as u32,
         xwin::Variant::Desktop as u32,
+        None,
+        None,
     )
     .unwrap();
 